@@ -9,6 +9,9 @@ with open(path) as f:
     data = json.load(f)
 
 f = open(path.with_suffix(".bulkstream"), 'wb')
+# stream offset of every transfer and its capture time in seconds
+ts = open(path.with_suffix(".timestamps"), 'w')
+offset = 0
 
 for pkt in data:
     usb = pkt['_source']['layers']['usb']
@@ -17,4 +20,8 @@ for pkt in data:
        usb['usb.transfer_type'] == '0x03' and \
        usb['usb.endpoint_address'] == '0x01':
         data = pkt['_source']['layers']['usb.capdata']
-        f.write(bytes.fromhex(data.replace(':', '')))
+        time = pkt['_source']['layers']['frame']['frame.time_relative']
+        capdata = bytes.fromhex(data.replace(':', ''))
+        ts.write(f"{offset} {time}\n")
+        f.write(capdata)
+        offset += len(capdata)
@@ -0,0 +1,402 @@
+use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::PathBuf;
+
+use crate::disasm::{number, reg_name};
+
+const LOAD_MAGIC: &str = "263871cd";
+
+#[derive(clap::Args)]
+pub struct AsmArgs {
+    /// Bulk stream to write
+    #[arg(short, long)]
+    output: PathBuf,
+
+    /// Text in `disasm --data` syntax, `-` reads stdin
+    #[arg(value_name = "FILE")]
+    path: PathBuf,
+}
+
+pub fn run(args: AsmArgs) {
+    let mut text = String::new();
+    if args.path.as_os_str() == "-" {
+        std::io::stdin()
+            .read_to_string(&mut text)
+            .expect("Failed to read stdin");
+    } else {
+        text = std::fs::read_to_string(&args.path).expect("Failed to open source");
+    }
+    match assemble(&text) {
+        Ok(stream) => std::fs::write(&args.output, stream).expect("Failed to write bulkstream"),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Assembles text in the syntax printed by `disasm --data` into a bulk
+/// stream. Offsets, `(...)` annotations and `;` comments are ignored, and
+/// addresses may be written as `(x,y)` in the framebuffer set so far.
+pub fn assemble(text: &str) -> Result<Vec<u8>, String> {
+    let mut asm = Assembler {
+        reg: [0u8; 256],
+        out: vec![],
+    };
+    for (i, line) in text.lines().enumerate() {
+        asm.line(line)
+            .map_err(|e| format!("line {}: {}", i + 1, e))?;
+    }
+    Ok(asm.out)
+}
+
+struct Assembler {
+    reg: [u8; 256],
+    out: Vec<u8>,
+}
+
+type Fields<'a> = HashMap<&'a str, &'a str>;
+
+fn field<'a>(fields: &Fields<'a>, key: &str) -> Result<&'a str, String> {
+    fields
+        .get(key)
+        .copied()
+        .ok_or_else(|| format!("missing `{}=`", key))
+}
+
+fn pixel(s: &str, is16: bool) -> Result<u16, String> {
+    let value = number(s)?;
+    let max = if is16 { 0xffff } else { 0xff };
+    if value > max {
+        return Err(format!("pixel `{}` out of range", s));
+    }
+    Ok(value as u16)
+}
+
+fn hex_bytes(s: &str) -> Result<Vec<u8>, String> {
+    if !s.len().is_multiple_of(2) {
+        return Err(format!("odd length hex `{}`", s));
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).map_err(|_| format!("bad hex `{}`", s)))
+        .collect()
+}
+
+fn put_pixel(out: &mut Vec<u8>, value: u16, is16: bool) {
+    if is16 {
+        out.write_u16::<BigEndian>(value).unwrap();
+    } else {
+        out.push(value as u8);
+    }
+}
+
+fn check_count(fields: &Fields, cnt: usize) -> Result<usize, String> {
+    if let Some(n) = fields.get("n") {
+        if number(n)? != cnt {
+            return Err(format!("n={} but data holds {}", n, cnt));
+        }
+    }
+    if !(1..=256).contains(&cnt) {
+        return Err(format!("count {} out of range", cnt));
+    }
+    Ok(cnt)
+}
+
+impl Assembler {
+    fn line(&mut self, line: &str) -> Result<(), String> {
+        let line = line.split(';').next().unwrap();
+        let mut tokens = line.split_whitespace();
+        let Some(mut mnemonic) = tokens.next() else {
+            return Ok(());
+        };
+        if mnemonic.chars().all(|c| c.is_ascii_hexdigit()) {
+            // offset column
+            mnemonic = tokens.next().ok_or("missing command")?;
+        }
+        let mut fields = Fields::new();
+        for token in tokens {
+            if token.starts_with('(') {
+                continue;
+            }
+            let (key, value) = token
+                .split_once('=')
+                .ok_or_else(|| format!("unexpected `{}`", token))?;
+            fields.insert(key, value);
+        }
+
+        match mnemonic.to_ascii_uppercase().as_str() {
+            "SETREG" => {
+                let reg = field(&fields, "reg")?;
+                let addr = match (0..=255).find(|&a| reg_name(a) == Some(reg)) {
+                    Some(addr) => addr,
+                    None => number(reg)?
+                        .try_into()
+                        .map_err(|_| format!("bad register `{}`", reg))?,
+                };
+                let val = field(&fields, "val")?;
+                let val = number(val)?
+                    .try_into()
+                    .map_err(|_| format!("bad value `{}`", val))?;
+                self.reg[addr as usize] = val;
+                self.out.extend([0xaf, 0x20, addr, val]);
+            }
+            "SYNC" => self.out.extend([0xaf, 0xa0]),
+            "NOP" => {
+                let padding = match fields.get("data") {
+                    Some(data) => hex_bytes(data)?,
+                    None => vec![0; number(field(&fields, "n")?)?],
+                };
+                if padding.contains(&0xaf) {
+                    return Err("padding cannot contain 0xaf".to_string());
+                }
+                self.out.extend(padding);
+            }
+            "LOADTABLE" => {
+                let data = hex_bytes(field(&fields, "data")?)?;
+                if !data.len().is_multiple_of(9) {
+                    return Err("table data is not a multiple of 9 bytes".to_string());
+                }
+                let magic = hex_bytes(fields.get("magic").unwrap_or(&LOAD_MAGIC))?;
+                if magic.len() != 4 {
+                    return Err("magic must be 4 bytes".to_string());
+                }
+                self.out.extend([0xaf, 0xe0]);
+                self.out.extend(magic);
+                self.out
+                    .write_u32::<BigEndian>((data.len() / 9) as u32)
+                    .unwrap();
+                self.out.extend(data);
+            }
+            other => {
+                let (name, is16) = if let Some(name) = other.strip_suffix("16") {
+                    (name, true)
+                } else if let Some(name) = other.strip_suffix('8') {
+                    (name, false)
+                } else {
+                    return Err(format!("unknown command `{}`", mnemonic));
+                };
+                self.pixel_cmd(name, is16, &fields)
+                    .map_err(|e| format!("{}: {}", mnemonic, e))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn address(&self, s: &str, is16: bool) -> Result<usize, String> {
+        let Some(pos) = s.strip_prefix('(').and_then(|s| s.strip_suffix(')')) else {
+            let addr = number(s)?;
+            if addr > 0xffffff {
+                return Err(format!("address `{}` out of range", s));
+            }
+            return Ok(addr);
+        };
+        let (x, y) = pos
+            .split_once(',')
+            .ok_or_else(|| format!("bad position `{}`", s))?;
+        let (x, y) = (number(x)?, number(y)?);
+        let width = BigEndian::read_u16(&self.reg[0x0f..0x11]) as usize;
+        let (base, bpp) = if is16 {
+            (BigEndian::read_u24(&self.reg[0x20..0x23]) as usize, 2)
+        } else {
+            (BigEndian::read_u24(&self.reg[0x26..0x29]) as usize, 1)
+        };
+        Ok(base + (y * width + x) * bpp)
+    }
+
+    fn pixel_cmd(&mut self, name: &str, is16: bool, fields: &Fields) -> Result<(), String> {
+        let (op, payload) = match name {
+            "RAW" => (0x60, Payload::Raw),
+            "FILL" => (0x61, Payload::Fill),
+            "COPY" => (0x62, Payload::Copy),
+            "RLX" => (0x63, Payload::Rlx),
+            "DECOMP" => (0x70, Payload::Decomp),
+            _ => return Err("unknown command".to_string()),
+        };
+        let op = if is16 { op | 0x08 } else { op };
+        let addr = self.address(field(fields, "dst")?, is16)?;
+        let items: Vec<&str> = match fields.get("data") {
+            Some(data) => data.split(',').collect(),
+            None => vec![],
+        };
+
+        let mut body = vec![];
+        let cnt = match payload {
+            Payload::Raw => {
+                for item in &items {
+                    put_pixel(&mut body, pixel(item, is16)?, is16);
+                }
+                items.len()
+            }
+            Payload::Fill => {
+                let mut cnt = 0;
+                for item in &items {
+                    let (runcnt, value) = item
+                        .split_once('*')
+                        .ok_or_else(|| format!("bad run `{}`", item))?;
+                    let runcnt = number(runcnt)?;
+                    if !(1..=256).contains(&runcnt) {
+                        return Err(format!("run `{}` out of range", item));
+                    }
+                    body.push(runcnt as u8);
+                    put_pixel(&mut body, pixel(value, is16)?, is16);
+                    cnt += runcnt;
+                }
+                cnt
+            }
+            Payload::Rlx => {
+                let mut cnt = 0;
+                let mut span = vec![];
+                for (i, item) in items.iter().enumerate() {
+                    let (value, repeat) = match item.split_once('+') {
+                        Some((value, repeat)) => (value, Some(number(repeat)?)),
+                        None => (*item, None),
+                    };
+                    span.push(pixel(value, is16)?);
+                    if repeat.is_none() && i + 1 < items.len() {
+                        continue;
+                    }
+                    if span.len() > 256 || repeat.is_some_and(|r| r > 255) {
+                        return Err(format!("span ending at `{}` too long", item));
+                    }
+                    body.push(span.len() as u8);
+                    for p in span.drain(..) {
+                        put_pixel(&mut body, p, is16);
+                        cnt += 1;
+                    }
+                    if let Some(repeat) = repeat {
+                        body.push(repeat as u8);
+                        cnt += repeat;
+                    }
+                }
+                cnt
+            }
+            Payload::Copy => {
+                let src = self.address(field(fields, "src")?, is16)?;
+                body.write_u24::<BigEndian>(src as u32).unwrap();
+                number(field(fields, "n")?)?
+            }
+            Payload::Decomp => {
+                body = hex_bytes(field(fields, "data")?)?;
+                number(field(fields, "n")?)?
+            }
+        };
+        let cnt = check_count(fields, cnt)?;
+
+        self.out.extend([0xaf, op]);
+        self.out.write_u24::<BigEndian>(addr as u32).unwrap();
+        // 256 wraps to 0
+        self.out.push(cnt as u8);
+        self.out.extend(body);
+        Ok(())
+    }
+}
+
+enum Payload {
+    Raw,
+    Fill,
+    Copy,
+    Rlx,
+    Decomp,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disasm::listing;
+    use crate::dlencoder::{DecompTable, DeltaStats};
+    use std::io::Cursor;
+
+    fn setreg(out: &mut Vec<u8>, addr: u8, val: u8) {
+        out.extend([0xaf, 0x20, addr, val]);
+    }
+
+    /// Every command, with padding between them and runs of 256 pixels.
+    fn stream() -> Vec<u8> {
+        let mut out = vec![];
+        // 32x8, 16-bit plane at 0x1000, 8-bit plane at 0x800000
+        for (addr, val) in [(0x00, 0), (0x0f, 0), (0x10, 32), (0x17, 0), (0x18, 8)] {
+            setreg(&mut out, addr, val);
+        }
+        for (addr, val) in [(0x20, 0), (0x21, 0x10), (0x22, 0), (0x26, 0x80), (0x27, 0)] {
+            setreg(&mut out, addr, val);
+        }
+        setreg(&mut out, 0x28, 0);
+        out.extend([0x00, 0x12, 0x00]);
+        out.extend([0xaf, 0xa0]);
+
+        let pixels: Vec<u16> = (0..256).map(|i| (i * 0x0841) as u16 % 4000).collect();
+        let mut stats16 = DeltaStats::default();
+        let mut stats8 = DeltaStats::default();
+        stats16.add_span16(&pixels[..64]);
+        stats8.add_span8(&pixels.iter().map(|&p| p as u8).collect::<Vec<_>>()[..64]);
+        let table = DecompTable::build(&stats16, &stats8);
+        table.write_load_cmd(&mut out).unwrap();
+
+        // raw
+        out.extend([
+            0xaf, 0x68, 0x00, 0x10, 0x02, 3, 0x12, 0x34, 0xff, 0xff, 0x00, 0x01,
+        ]);
+        out.extend([0xaf, 0x60, 0x80, 0x00, 0x04, 2, 0x7f, 0xaf]);
+        // fill, 0 is 256
+        out.extend([0xaf, 0x69, 0x00, 0x10, 0x40, 0, 0, 0x12, 0x34]);
+        out.extend([0xaf, 0x61, 0x80, 0x00, 0x20, 5, 2, 7, 3, 9]);
+        out.push(0x55);
+        // copy
+        out.extend([0xaf, 0x6a, 0x00, 0x12, 0x00, 0, 0x00, 0x10, 0x00]);
+        out.extend([0xaf, 0x62, 0x80, 0x01, 0x00, 16, 0x80, 0x00, 0x00]);
+        // rlx, a repeated span then one filling the count
+        out.extend([0xaf, 0x6b, 0x00, 0x10, 0x00, 15, 3]);
+        out.extend([
+            0x00, 0x01, 0x00, 0x02, 0x00, 0x03, 10, 2, 0xab, 0xcd, 0xef, 0x01,
+        ]);
+        out.extend([0xaf, 0x63, 0x80, 0x00, 0x00, 0, 0]);
+        out.extend((0..=255).map(|i: u8| i.wrapping_mul(7)));
+        // decomp
+        table.write_decomp16(&mut out, 0x1200, &pixels).unwrap();
+        let pixels8: Vec<u8> = pixels.iter().map(|&p| (p >> 4) as u8).collect();
+        table.write_decomp8(&mut out, 0x80_0100, &pixels8).unwrap();
+        // commit
+        setreg(&mut out, 0x1f, 0);
+        setreg(&mut out, 0xff, 0xff);
+        out.extend([0x00; 4]);
+        out
+    }
+
+    #[test]
+    fn listing_assembles_to_the_same_stream() {
+        let stream = stream();
+        let mut text = vec![];
+        listing(&mut Cursor::new(stream.clone()), &mut text, true);
+        let text = String::from_utf8(text).unwrap();
+        for mnemonic in [
+            "NOP",
+            "SYNC",
+            "LOADTABLE",
+            "RAW16",
+            "RAW8",
+            "FILL16",
+            "FILL8",
+            "COPY16",
+            "COPY8",
+            "RLX16",
+            "RLX8",
+            "DECOMP16",
+            "DECOMP8",
+        ] {
+            let found = text
+                .lines()
+                .any(|line| line.split_whitespace().nth(1) == Some(mnemonic));
+            assert!(found, "{} missing", mnemonic);
+        }
+        assert!(text.contains("+10"), "no RLX repeat in\n{}", text);
+        assert_eq!(assemble(&text).unwrap(), stream, "listing:\n{}", text);
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        let err = assemble("SYNC\nFILL16 dst=0 data=300*0x1234\n").unwrap_err();
+        assert!(err.starts_with("line 2: FILL16: "), "{}", err);
+    }
+}
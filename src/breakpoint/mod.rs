@@ -0,0 +1,172 @@
+use std::fmt;
+use std::io::BufRead;
+use std::ops::Range;
+use std::str::FromStr;
+
+use crate::disasm::{mnemonic, number, position, reg_name};
+use crate::dldecoder::{DLDecoder, DLDecoderResult};
+use crate::frame::{Frame, FrameDecoder};
+
+/// What a breakpoint stops on, written as `op FILL16`, `op 0x69`,
+/// `write 0x0..0x1000`, `rect 0,0,64,32`, `reg blank_mode`,
+/// `reg 0x20..0x29`, `table` or `offset 0x1a3c`. Ranges are half-open.
+#[derive(Clone)]
+pub enum Condition {
+    Opcode(u8),
+    Mnemonic(String),
+    Write(Range<usize>),
+    Rect {
+        x: usize,
+        y: usize,
+        w: usize,
+        h: usize,
+    },
+    Reg(Range<usize>),
+    Table,
+    Offset(u64),
+}
+
+#[derive(Clone)]
+pub struct Breakpoint {
+    pub condition: Condition,
+    pub enabled: bool,
+}
+
+fn range(s: &str) -> Result<Range<usize>, String> {
+    match s.split_once("..") {
+        Some((start, end)) => Ok(number(start)?..number(end)?),
+        None => {
+            let start = number(s)?;
+            Ok(start..start + 1)
+        }
+    }
+}
+
+pub fn register(s: &str) -> Result<usize, String> {
+    match (0..=255).find(|&a| reg_name(a) == Some(s)) {
+        Some(addr) => Ok(addr as usize),
+        None => number(s),
+    }
+}
+
+impl FromStr for Condition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let (kind, arg) = s.trim().split_once(' ').unwrap_or((s.trim(), ""));
+        let arg = arg.trim();
+        let condition = match kind {
+            "op" if arg.starts_with("0x") => {
+                let op = number(arg)?;
+                Condition::Opcode(op.try_into().map_err(|_| format!("bad opcode `{}`", arg))?)
+            }
+            "op" if !arg.is_empty() => Condition::Mnemonic(arg.to_ascii_uppercase()),
+            "write" => Condition::Write(range(arg)?),
+            "rect" => {
+                let v = arg.split(',').map(number).collect::<Result<Vec<_>, _>>()?;
+                let [x, y, w, h] = v[..] else {
+                    return Err("expected `rect x,y,w,h`".to_string());
+                };
+                Condition::Rect { x, y, w, h }
+            }
+            "reg" => match arg.split_once("..") {
+                Some((start, end)) => Condition::Reg(register(start)?..register(end)?),
+                None => {
+                    let reg = register(arg)?;
+                    Condition::Reg(reg..reg + 1)
+                }
+            },
+            "table" => Condition::Table,
+            "offset" => Condition::Offset(number(arg)? as u64),
+            _ => return Err(format!("unknown breakpoint `{}`", s)),
+        };
+        Ok(condition)
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Condition::Opcode(op) => write!(f, "op 0x{:02x}", op),
+            Condition::Mnemonic(name) => write!(f, "op {}", name),
+            Condition::Write(r) => write!(f, "write 0x{:06x}..0x{:06x}", r.start, r.end),
+            Condition::Rect { x, y, w, h } => write!(f, "rect {},{},{},{}", x, y, w, h),
+            Condition::Reg(r) => write!(f, "reg 0x{:02x}..0x{:02x}", r.start, r.end),
+            Condition::Table => write!(f, "table"),
+            Condition::Offset(offset) => write!(f, "offset 0x{:x}", offset),
+        }
+    }
+}
+
+fn overlaps(a: &Range<usize>, b: &Range<usize>) -> bool {
+    a.start < b.end && b.start < a.end
+}
+
+impl Condition {
+    /// Whether the command `decoder_ctx` just executed meets the condition.
+    pub fn hits(&self, decoder_ctx: &DLDecoder, result: &DLDecoderResult) -> bool {
+        match self {
+            Condition::Opcode(op) => {
+                let bytes = decoder_ctx.get_cmd_bytes();
+                bytes.len() > 1 && bytes[0] == 0xaf && bytes[1] == *op
+            }
+            Condition::Mnemonic(name) => mnemonic(result) == *name,
+            Condition::Write(r) => result.written().is_some_and(|(w, _)| overlaps(&w, r)),
+            Condition::Rect { x, y, w, h } => {
+                let Some((bytes, is16)) = result.written() else {
+                    return false;
+                };
+                let width = decoder_ctx.get_width();
+                let Some((sx, sy)) = position(decoder_ctx, bytes.start, is16) else {
+                    return false;
+                };
+                // walk the rows the span covers
+                let bpp = if is16 { 2 } else { 1 };
+                let (mut px, mut py, mut left) = (sx, sy, bytes.len() / bpp);
+                while left > 0 && py < y + h {
+                    let n = left.min(width - px);
+                    if py >= *y && overlaps(&(px..px + n), &(*x..x + w)) {
+                        return true;
+                    }
+                    left -= n;
+                    px = 0;
+                    py += 1;
+                }
+                false
+            }
+            Condition::Reg(r) => match *result {
+                DLDecoderResult::Setreg(addr, _) => r.contains(&(addr as usize)),
+                _ => false,
+            },
+            Condition::Table => matches!(result, DLDecoderResult::LoadTable(_)),
+            Condition::Offset(offset) => {
+                let start = decoder_ctx.get_offset();
+                (start..decoder_ctx.get_next_offset()).contains(offset)
+            }
+        }
+    }
+}
+
+/// Where `run` stopped.
+pub enum Stop {
+    Frame(Box<Frame>),
+    /// Index of the breakpoint and the command that hit it, with the frame
+    /// that command committed, if it did.
+    Break(usize, DLDecoderResult, Option<Box<Frame>>),
+}
+
+/// Decodes up to the next commit, or until an enabled breakpoint hits.
+pub fn run<R: BufRead>(frames: &mut FrameDecoder<R>, breakpoints: &[Breakpoint]) -> Option<Stop> {
+    loop {
+        let (result, frame) = frames.step()?;
+        let hit = breakpoints
+            .iter()
+            .position(|bp| bp.enabled && bp.condition.hits(frames.decoder(), &result));
+        if let Some(i) = hit {
+            return Some(Stop::Break(i, result, frame.map(Box::new)));
+        }
+        if let Some(frame) = frame {
+            return Some(Stop::Frame(Box::new(frame)));
+        }
+    }
+}
@@ -0,0 +1,323 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, ErrorKind, Write};
+use std::path::PathBuf;
+
+use crate::dldecoder::{wrap256, DLDecoder, DLDecoderResult};
+
+#[derive(clap::Args)]
+pub struct DisasmArgs {
+    /// Also print command payloads, as accepted by `asm`
+    #[arg(short, long)]
+    data: bool,
+
+    #[arg(value_name = "FILE")]
+    path: PathBuf,
+}
+
+/// Parses a number as printed in listings, decimal or `0x` hex.
+pub fn number(s: &str) -> Result<usize, String> {
+    let parsed = match s.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    parsed.map_err(|_| format!("bad number `{}`", s))
+}
+
+/// Name of a video register, with the byte for multi-byte registers.
+pub fn reg_name(addr: u8) -> Option<&'static str> {
+    let name = match addr {
+        0x00 => "color_depth",
+        0x01 => "x_display_start.hi",
+        0x02 => "x_display_start.lo",
+        0x03 => "x_display_end.hi",
+        0x04 => "x_display_end.lo",
+        0x05 => "y_display_start.hi",
+        0x06 => "y_display_start.lo",
+        0x07 => "y_display_end.hi",
+        0x08 => "y_display_end.lo",
+        0x09 => "x_end_count.hi",
+        0x0a => "x_end_count.lo",
+        0x0b => "hsync_start.hi",
+        0x0c => "hsync_start.lo",
+        0x0d => "hsync_end.hi",
+        0x0e => "hsync_end.lo",
+        0x0f => "width.hi",
+        0x10 => "width.lo",
+        0x11 => "y_end_count.hi",
+        0x12 => "y_end_count.lo",
+        0x13 => "vsync_start.hi",
+        0x14 => "vsync_start.lo",
+        0x15 => "vsync_end.hi",
+        0x16 => "vsync_end.lo",
+        0x17 => "height.hi",
+        0x18 => "height.lo",
+        0x1b => "pixel_clock.lo",
+        0x1c => "pixel_clock.hi",
+        0x1f => "blank_mode",
+        0x20 => "base16.hi",
+        0x21 => "base16.mid",
+        0x22 => "base16.lo",
+        0x26 => "base8.hi",
+        0x27 => "base8.mid",
+        0x28 => "base8.lo",
+        0xff => "sync",
+        _ => return None,
+    };
+    Some(name)
+}
+
+/// Screen position of `addr` in the framebuffer currently set in registers.
+pub fn position(decoder: &DLDecoder, addr: usize, is16: bool) -> Option<(usize, usize)> {
+    let (base, bpp) = if is16 {
+        (decoder.get_current_address_16(), 2)
+    } else {
+        (decoder.get_current_address_8(), 1)
+    };
+    let w = decoder.get_width();
+    let idx = addr.checked_sub(base)? / bpp;
+    if w == 0 || idx >= w * decoder.get_height() {
+        return None;
+    }
+    Some((idx % w, idx / w))
+}
+
+fn format_addr(decoder: &DLDecoder, addr: usize, is16: bool) -> String {
+    match position(decoder, addr, is16) {
+        Some((x, y)) => format!("0x{:06x} ({},{})", addr, x, y),
+        None => format!("0x{:06x}", addr),
+    }
+}
+
+fn depth(is16: bool) -> u8 {
+    if is16 {
+        16
+    } else {
+        8
+    }
+}
+
+/// Mnemonic of a command as printed by the disassembler.
+pub fn mnemonic(result: &DLDecoderResult) -> String {
+    match *result {
+        DLDecoderResult::Fill(_, _, is16) => format!("FILL{}", depth(is16)),
+        DLDecoderResult::Memcpy(_, _, is16, _) => format!("COPY{}", depth(is16)),
+        DLDecoderResult::Decomp(_, _, is16) => format!("DECOMP{}", depth(is16)),
+        DLDecoderResult::Raw(_, _, is16) => format!("RAW{}", depth(is16)),
+        DLDecoderResult::Rlx(_, _, is16) => format!("RLX{}", depth(is16)),
+        DLDecoderResult::Setreg(..) => "SETREG".to_string(),
+        DLDecoderResult::LoadTable(_) => "LOADTABLE".to_string(),
+        DLDecoderResult::Sync => "SYNC".to_string(),
+        DLDecoderResult::Noop => "NOP".to_string(),
+    }
+}
+
+/// Formats the command `decoder` just parsed, without its offset.
+pub fn disasm(decoder: &DLDecoder, result: &DLDecoderResult) -> String {
+    let bytes = decoder.get_cmd_bytes();
+    let name = mnemonic(result);
+    match *result {
+        DLDecoderResult::Fill(addr, len, is16) => {
+            let bpp = if is16 { 2 } else { 1 };
+            let runs = (bytes.len() - 6) / (1 + bpp);
+            format!(
+                "{} dst={} n={} runs={}",
+                name,
+                format_addr(decoder, addr, is16),
+                len,
+                runs
+            )
+        }
+        DLDecoderResult::Memcpy(addr, len, is16, src) => format!(
+            "{} dst={} n={} src={}",
+            name,
+            format_addr(decoder, addr, is16),
+            len,
+            format_addr(decoder, src, is16)
+        ),
+        DLDecoderResult::Decomp(addr, len, is16) | DLDecoderResult::Rlx(addr, len, is16) => {
+            format!(
+                "{} dst={} n={} bytes={}",
+                name,
+                format_addr(decoder, addr, is16),
+                len,
+                bytes.len() - 6
+            )
+        }
+        DLDecoderResult::Raw(addr, len, is16) => format!(
+            "{} dst={} n={}",
+            name,
+            format_addr(decoder, addr, is16),
+            len
+        ),
+        DLDecoderResult::Setreg(addr, val) => match reg_name(addr) {
+            Some(reg) => format!("{} reg=0x{:02x} ({}) val=0x{:02x}", name, addr, reg, val),
+            None => format!("{} reg=0x{:02x} val=0x{:02x}", name, addr, val),
+        },
+        DLDecoderResult::LoadTable(cnt) => format!("{} n={}", name, cnt),
+        DLDecoderResult::Sync => name,
+        DLDecoderResult::Noop => format!("{} n=1", name),
+    }
+}
+
+/// Formats a command from its result alone, leaving out what needs the
+/// command bytes.
+pub fn describe(decoder: &DLDecoder, result: &DLDecoderResult) -> String {
+    let name = mnemonic(result);
+    match *result {
+        DLDecoderResult::Memcpy(addr, len, is16, src) => format!(
+            "{} dst={} n={} src={}",
+            name,
+            format_addr(decoder, addr, is16),
+            len,
+            format_addr(decoder, src, is16)
+        ),
+        DLDecoderResult::Fill(addr, len, is16)
+        | DLDecoderResult::Decomp(addr, len, is16)
+        | DLDecoderResult::Raw(addr, len, is16)
+        | DLDecoderResult::Rlx(addr, len, is16) => format!(
+            "{} dst={} n={}",
+            name,
+            format_addr(decoder, addr, is16),
+            len
+        ),
+        _ => disasm(decoder, result),
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn format_pixel(bytes: &[u8], is16: bool) -> String {
+    if is16 {
+        format!("0x{:02x}{:02x}", bytes[0], bytes[1])
+    } else {
+        format!("0x{:02x}", bytes[0])
+    }
+}
+
+/// Formats the payload of the command `decoder` just parsed, if it has one.
+pub fn disasm_data(decoder: &DLDecoder, result: &DLDecoderResult) -> Option<String> {
+    let bytes = decoder.get_cmd_bytes();
+    let data = match *result {
+        DLDecoderResult::Fill(_, _, is16) => {
+            let bpp = if is16 { 2 } else { 1 };
+            bytes[6..]
+                .chunks(1 + bpp)
+                .map(|run| format!("{}*{}", wrap256(run[0]), format_pixel(&run[1..], is16)))
+                .collect::<Vec<_>>()
+                .join(",")
+        }
+        DLDecoderResult::Raw(_, _, is16) => {
+            let bpp = if is16 { 2 } else { 1 };
+            bytes[6..]
+                .chunks(bpp)
+                .map(|p| format_pixel(p, is16))
+                .collect::<Vec<_>>()
+                .join(",")
+        }
+        DLDecoderResult::Rlx(_, len, is16) => {
+            // raw pixels, a `+N` suffix repeats the last one and ends the span
+            let bpp = if is16 { 2 } else { 1 };
+            let mut items = vec![];
+            let mut pos = 0;
+            let mut i = 6;
+            while pos < len {
+                let rawcnt = wrap256(bytes[i]);
+                i += 1;
+                for _ in 0..rawcnt {
+                    items.push(format_pixel(&bytes[i..i + bpp], is16));
+                    i += bpp;
+                }
+                pos += rawcnt;
+                if pos < len {
+                    let last = items.last_mut().unwrap();
+                    *last = format!("{}+{}", last, bytes[i]);
+                    pos += bytes[i] as usize;
+                    i += 1;
+                }
+            }
+            items.join(",")
+        }
+        DLDecoderResult::Decomp(..) => hex(&bytes[6..]),
+        DLDecoderResult::LoadTable(_) => {
+            format!("{} magic={}", hex(&bytes[10..]), hex(&bytes[2..6]))
+        }
+        _ => return None,
+    };
+    Some(data)
+}
+
+pub fn run(args: DisasmArgs) {
+    let bulkstream_f = File::open(args.path).expect("Failed to open bulkstream");
+    let mut bulkstream = BufReader::new(bulkstream_f);
+    let mut out = BufWriter::new(std::io::stdout().lock());
+    listing(&mut bulkstream, &mut out, args.data);
+}
+
+/// Writes one line per command of `bulkstream` to `out`, with the payloads
+/// in `asm` syntax if `data` is set.
+pub fn listing(bulkstream: &mut dyn BufRead, out: &mut dyn Write, data: bool) {
+    let mut decoder_ctx = DLDecoder::default();
+    let mut framecnt = 0;
+    // padding bytes are folded into one line
+    let mut noop: Option<(u64, Vec<u8>)> = None;
+
+    let _ = writeln!(out, "; frame {}", framecnt);
+    loop {
+        let result = match decoder_ctx.parse_cmd(bulkstream) {
+            Ok(result) => result,
+            Err(e) if e.kind() == ErrorKind::InvalidData => {
+                eprintln!("{:08x}: invalid command: {}", decoder_ctx.get_offset(), e);
+                break;
+            }
+            Err(_) if !decoder_ctx.get_cmd_bytes().is_empty() => {
+                eprintln!("{:08x}: truncated command", decoder_ctx.get_offset());
+                break;
+            }
+            // end of stream
+            Err(_) => break,
+        };
+        if let DLDecoderResult::Noop = result {
+            let offset = decoder_ctx.get_offset();
+            let padding = &mut noop.get_or_insert((offset, vec![])).1;
+            padding.extend_from_slice(decoder_ctx.get_cmd_bytes());
+            continue;
+        }
+        if let Some((offset, padding)) = noop.take() {
+            if write_noop(out, offset, &padding, data).is_err() {
+                return;
+            }
+        }
+        let mut line = disasm(&decoder_ctx, &result);
+        if data {
+            if let Some(payload) = disasm_data(&decoder_ctx, &result) {
+                line = format!("{} data={}", line, payload);
+            }
+        }
+        if writeln!(out, "{:08x} {}", decoder_ctx.get_offset(), line).is_err() {
+            return;
+        }
+        if matches!(result, DLDecoderResult::Setreg(0xff, 0xff)) && decoder_ctx.get_reg(0x1f) == 0 {
+            framecnt += 1;
+            let _ = writeln!(out, "; frame {}", framecnt);
+        }
+    }
+    if let Some((offset, padding)) = noop {
+        let _ = write_noop(out, offset, &padding, data);
+    }
+}
+
+fn write_noop(out: &mut dyn Write, offset: u64, padding: &[u8], data: bool) -> std::io::Result<()> {
+    if data {
+        writeln!(
+            out,
+            "{:08x} NOP n={} data={}",
+            offset,
+            padding.len(),
+            hex(padding)
+        )
+    } else {
+        writeln!(out, "{:08x} NOP n={}", offset, padding.len())
+    }
+}
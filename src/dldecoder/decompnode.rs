@@ -27,4 +27,15 @@ impl DecompNode {
             },
         ]
     }
+
+    pub fn write_to(nodes: &[DecompNode; 2], buf: &mut [u8; 9]) {
+        let [a, b] = nodes;
+        BigEndian::write_u16(&mut buf[0..2], a.color);
+        buf[2] = 0;
+        buf[3] = ((a.next >> 4) & 0x1f) as u8;
+        buf[4] = ((a.next & 0xf) as u8) << 4 | (b.next & 0xf) as u8;
+        BigEndian::write_u16(&mut buf[5..7], b.color);
+        buf[7] = 0;
+        buf[8] = ((b.next >> 4) & 0x1f) as u8;
+    }
 }
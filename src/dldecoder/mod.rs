@@ -1,10 +1,20 @@
 use byteorder::{BigEndian, ByteOrder, ReadBytesExt};
 use std::io::prelude::*;
+use std::ops::Range;
 
 mod decompnode;
-use decompnode::DecompNode;
+pub use decompnode::DecompNode;
 
-fn wrap256(n: u8) -> usize {
+mod provenance;
+pub use provenance::RamWrite;
+
+mod snapshot;
+pub use snapshot::SNAPSHOT_MAGIC;
+
+mod usage;
+pub use usage::{Region, BLOCK_SIZE};
+
+pub fn wrap256(n: u8) -> usize {
     if n == 0 {
         256
     } else {
@@ -12,18 +22,57 @@ fn wrap256(n: u8) -> usize {
     }
 }
 
+/// What a parsed command did. Pixel commands give the start address, the
+/// pixel count and whether they write the 16-bit plane.
+#[derive(Clone, Copy)]
 pub enum DLDecoderResult {
+    /// Start and length of the whole command, not of its last run.
     Fill(usize, usize, bool),
-    Memcpy(usize, usize, bool),
+    /// Destination, count, plane and source address.
+    Memcpy(usize, usize, bool, usize),
     Decomp(usize, usize, bool),
+    Raw(usize, usize, bool),
+    Rlx(usize, usize, bool),
     Setreg(u8, u8),
+    /// Number of table entries loaded.
+    LoadTable(usize),
+    /// 0xaf 0xa0, which used to be reported as `Noop`.
+    Sync,
+    /// A byte outside a command.
     Noop,
 }
 
+impl DLDecoderResult {
+    /// Bytes of `gfxram` the command wrote, and whether they hold 16-bit
+    /// pixels.
+    pub fn written(&self) -> Option<(Range<usize>, bool)> {
+        match *self {
+            DLDecoderResult::Fill(addr, len, is16)
+            | DLDecoderResult::Memcpy(addr, len, is16, _)
+            | DLDecoderResult::Decomp(addr, len, is16)
+            | DLDecoderResult::Raw(addr, len, is16)
+            | DLDecoderResult::Rlx(addr, len, is16) => {
+                let bpp = if is16 { 2 } else { 1 };
+                Some((addr..addr + len * bpp, is16))
+            }
+            _ => None,
+        }
+    }
+}
+
 pub struct DLDecoder {
     gfxram: Vec<u8>,
     reg: [u8; 256],
     decomp_table: [[DecompNode; 2]; 512],
+    offset: u64,
+    cmdbuf: Vec<u8>,
+    frames: u64,
+    // framebuffer addresses at the last commit, and the ones shown before
+    // they last changed
+    front: Option<(usize, usize)>,
+    back: Option<(usize, usize)>,
+    provenance: Option<provenance::Provenance>,
+    usage: Option<usage::Usage>,
 }
 
 impl Default for DLDecoder {
@@ -32,11 +81,34 @@ impl Default for DLDecoder {
             gfxram: vec![0u8; 0x100_0000],
             reg: [0u8; 256],
             decomp_table: [[DecompNode { color: 0, next: 0 }; 2]; 512],
+            offset: 0,
+            cmdbuf: vec![],
+            frames: 0,
+            front: None,
+            back: None,
+            provenance: None,
+            usage: None,
         }
     }
 }
 
 impl DLDecoder {
+    /// Copy of the state for looking at, without the provenance record.
+    pub fn view_copy(&self) -> DLDecoder {
+        DLDecoder {
+            gfxram: self.gfxram.clone(),
+            reg: self.reg,
+            decomp_table: self.decomp_table,
+            offset: self.offset,
+            cmdbuf: self.cmdbuf.clone(),
+            frames: self.frames,
+            front: self.front,
+            back: self.back,
+            provenance: None,
+            usage: self.usage.clone(),
+        }
+    }
+
     pub fn dumpbuffer(&self, buf: &mut [u8], addr: usize, len: usize) {
         // check wrapping
         if addr + len > 0xffffff {
@@ -52,7 +124,8 @@ impl DLDecoder {
         buf.copy_from_slice(&self.reg[..]);
     }
 
-    pub fn get_reg(&mut self, addr: u8) -> u8 {
+    /// Value of register `addr`.
+    pub fn get_reg(&self, addr: u8) -> u8 {
         self.reg[addr as usize]
     }
     pub fn get_width(&self) -> usize {
@@ -68,10 +141,86 @@ impl DLDecoder {
         BigEndian::read_u24(&self.reg[0x26..0x29]) as usize
     }
 
+    /// Number of frames committed so far.
+    pub fn get_frame_count(&self) -> u64 {
+        self.frames
+    }
+
+    /// 16-bit and 8-bit plane addresses of the framebuffer shown at the last
+    /// commit.
+    pub fn get_front_buffer(&self) -> Option<(usize, usize)> {
+        self.front
+    }
+    /// Addresses shown before the last page flip, the buffer a double
+    /// buffering driver draws the next frame into. Not kept in snapshots.
+    pub fn get_back_buffer(&self) -> Option<(usize, usize)> {
+        self.back
+    }
+
+    /// Stream offset of the last parsed command.
+    pub fn get_offset(&self) -> u64 {
+        self.offset
+    }
+    /// Raw bytes of the last parsed command.
+    pub fn get_cmd_bytes(&self) -> &[u8] {
+        &self.cmdbuf
+    }
+    /// Stream offset the next command is read from.
+    pub fn get_next_offset(&self) -> u64 {
+        self.offset + self.cmdbuf.len() as u64
+    }
+    /// Continues counting offsets from `offset`, for reading a stream
+    /// from the middle.
+    pub fn set_next_offset(&mut self, offset: u64) {
+        self.offset = offset;
+        self.cmdbuf.clear();
+    }
+
+    /// Writes `gfxram`, the registers and the decompression table (512
+    /// entries in the encoding of the load command), in that order.
+    pub fn write_ramdump(&self, writer: &mut dyn Write) -> std::io::Result<()> {
+        writer.write_all(&self.gfxram)?;
+        writer.write_all(&self.reg)?;
+        let mut nodebuf = [0u8; 9];
+        for nodes in &self.decomp_table {
+            DecompNode::write_to(nodes, &mut nodebuf);
+            writer.write_all(&nodebuf)?;
+        }
+        Ok(())
+    }
+    /// Restores the state written by `write_ramdump`.
+    pub fn read_ramdump(reader: &mut dyn Read) -> std::io::Result<Self> {
+        let mut decoder = Self::default();
+        reader.read_exact(&mut decoder.gfxram)?;
+        reader.read_exact(&mut decoder.reg)?;
+        let mut nodebuf = [0u8; 9];
+        for nodes in decoder.decomp_table.iter_mut() {
+            reader.read_exact(&mut nodebuf)?;
+            *nodes = DecompNode::read_from(&nodebuf);
+        }
+        Ok(decoder)
+    }
+
     pub fn parse_cmd(
         &mut self,
         reader: &mut dyn BufRead,
     ) -> Result<DLDecoderResult, std::io::Error> {
+        self.offset += self.cmdbuf.len() as u64;
+        let mut cmdbuf = std::mem::take(&mut self.cmdbuf);
+        cmdbuf.clear();
+        let result = self.execute_cmd(&mut Recorder {
+            inner: reader,
+            buf: &mut cmdbuf,
+        });
+        self.cmdbuf = cmdbuf;
+        if let Ok(result) = &result {
+            self.record_write(result);
+            self.record_usage(result);
+        }
+        result
+    }
+
+    fn execute_cmd(&mut self, reader: &mut dyn BufRead) -> Result<DLDecoderResult, std::io::Error> {
         match reader.read_u8() {
             Ok(n) => {
                 if n != 0xaf {
@@ -83,22 +232,34 @@ impl DLDecoder {
                 _ => panic!("Cannot read: {}", e),
             },
         };
-        match reader.read_u8().unwrap() {
+        match reader.read_u8()? {
             // set register
             0x20 => self.cmd_setreg(reader),
 
+            // raw 8bit
+            0x60 => self.cmd_raw8(reader),
+
             // fill 8bit
             0x61 => self.cmd_fill8(reader),
 
             // memcpy 8bit
             0x62 => self.cmd_memcopy8(reader),
 
+            // rlx 8bit
+            0x63 => self.cmd_rlx8(reader),
+
+            // raw 16bit
+            0x68 => self.cmd_raw16(reader),
+
             // fill 16bit
             0x69 => self.cmd_fill16(reader),
 
             // memcpy 16bit
             0x6a => self.cmd_memcopy16(reader),
 
+            // rlx 16bit
+            0x6b => self.cmd_rlx16(reader),
+
             // decompress 8bit
             0x70 => self.cmd_decomp8(reader),
 
@@ -108,10 +269,11 @@ impl DLDecoder {
             // load decompression table
             0xe0 => self.cmd_load_decomp(reader),
 
-            0xa0 => Ok(DLDecoderResult::Noop),
-            i => {
-                panic!("Unexpected command: {:x}", i)
-            }
+            0xa0 => Ok(DLDecoderResult::Sync),
+            i => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("unexpected command: {:x}", i),
+            )),
         }
     }
 }
@@ -121,6 +283,15 @@ impl DLDecoder {
         let addr = reader.read_u8()?;
         let val = reader.read_u8()?;
         self.reg[addr as usize] = val;
+        if addr == 0xff && val == 0xff && self.reg[0x1f] == 0 {
+            self.frames += 1;
+            let front = (self.get_current_address_16(), self.get_current_address_8());
+            if self.front.is_some_and(|f| f != front) {
+                // page flip
+                self.back = self.front;
+            }
+            self.front = Some(front);
+        }
         Ok(DLDecoderResult::Setreg(addr, val))
     }
 
@@ -128,14 +299,15 @@ impl DLDecoder {
         &mut self,
         reader: &mut dyn BufRead,
     ) -> Result<DLDecoderResult, std::io::Error> {
-        reader.consume(4);
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
         let cnt = reader.read_u32::<BigEndian>()?;
         let mut nodebuf = [0u8; 9];
         for i in 0..cnt {
             reader.read_exact(&mut nodebuf)?;
             self.decomp_table[i as usize] = DecompNode::read_from(&nodebuf);
         }
-        Ok(DLDecoderResult::Noop)
+        Ok(DLDecoderResult::LoadTable(cnt as usize))
     }
 
     fn cmd_memcopy8(
@@ -145,8 +317,8 @@ impl DLDecoder {
         let dstaddr = reader.read_u24::<BigEndian>()? as usize;
         let cnt = wrap256(reader.read_u8()?);
         let srcaddr = reader.read_u24::<BigEndian>()? as usize;
-        self.gfxram.copy_within(srcaddr..srcaddr + cnt, dstaddr);
-        Ok(DLDecoderResult::Memcpy(dstaddr, cnt, false))
+        self.copy(dstaddr, srcaddr, cnt);
+        Ok(DLDecoderResult::Memcpy(dstaddr, cnt, false, srcaddr))
     }
 
     fn cmd_memcopy16(
@@ -156,40 +328,117 @@ impl DLDecoder {
         let dstaddr = reader.read_u24::<BigEndian>()? as usize;
         let cnt = wrap256(reader.read_u8()?);
         let srcaddr = reader.read_u24::<BigEndian>()? as usize;
-        self.gfxram.copy_within(srcaddr..srcaddr + cnt * 2, dstaddr);
-        Ok(DLDecoderResult::Memcpy(dstaddr, cnt, true))
+        self.copy(dstaddr, srcaddr, cnt * 2);
+        Ok(DLDecoderResult::Memcpy(dstaddr, cnt, true, srcaddr))
     }
 
     fn cmd_fill8(&mut self, reader: &mut dyn BufRead) -> Result<DLDecoderResult, std::io::Error> {
-        let mut addr = reader.read_u24::<BigEndian>()? as usize;
-        let mut totalcnt = wrap256(reader.read_u8()?);
+        let start = reader.read_u24::<BigEndian>()? as usize;
+        let len = wrap256(reader.read_u8()?);
+        let mut addr = start;
+        let mut totalcnt = len;
         while totalcnt > 0 {
             let cnt = wrap256(reader.read_u8()?);
             let value = reader.read_u8()?;
+            check_span(cnt, totalcnt)?;
             for i in 0..cnt {
-                self.gfxram[addr + i] = value;
+                self.set8(addr + i, value);
             }
             totalcnt -= cnt;
             addr += cnt;
         }
-        Ok(DLDecoderResult::Fill(addr - totalcnt, totalcnt, false))
+        Ok(DLDecoderResult::Fill(start, len, false))
     }
 
     fn cmd_fill16(&mut self, reader: &mut dyn BufRead) -> Result<DLDecoderResult, std::io::Error> {
-        let mut addr = reader.read_u24::<BigEndian>()? as usize;
-        let mut totalcnt = wrap256(reader.read_u8()?);
+        let start = reader.read_u24::<BigEndian>()? as usize;
+        let len = wrap256(reader.read_u8()?);
+        let mut addr = start;
+        let mut totalcnt = len;
         while totalcnt > 0 {
             let cnt = wrap256(reader.read_u8()?);
-            let mut value = [0u8; 2];
-            reader.read_exact(&mut value)?;
+            let value = reader.read_u16::<BigEndian>()?;
+            check_span(cnt, totalcnt)?;
             for i in 0..cnt {
-                self.gfxram[addr + i * 2] = value[1];
-                self.gfxram[addr + i * 2 + 1] = value[0];
+                self.set16(addr + i * 2, value);
             }
             totalcnt -= cnt;
             addr += cnt * 2;
         }
-        Ok(DLDecoderResult::Fill(addr - totalcnt * 2, totalcnt, true))
+        Ok(DLDecoderResult::Fill(start, len, true))
+    }
+
+    fn cmd_raw8(&mut self, reader: &mut dyn BufRead) -> Result<DLDecoderResult, std::io::Error> {
+        let addr = reader.read_u24::<BigEndian>()? as usize;
+        let cnt = wrap256(reader.read_u8()?);
+        let mut values = [0u8; 256];
+        reader.read_exact(&mut values[..cnt])?;
+        for (i, &value) in values[..cnt].iter().enumerate() {
+            self.set8(addr + i, value);
+        }
+        Ok(DLDecoderResult::Raw(addr, cnt, false))
+    }
+
+    fn cmd_raw16(&mut self, reader: &mut dyn BufRead) -> Result<DLDecoderResult, std::io::Error> {
+        let addr = reader.read_u24::<BigEndian>()? as usize;
+        let cnt = wrap256(reader.read_u8()?);
+        for i in 0..cnt {
+            let value = reader.read_u16::<BigEndian>()?;
+            self.set16(addr + i * 2, value);
+        }
+        Ok(DLDecoderResult::Raw(addr, cnt, true))
+    }
+
+    fn cmd_rlx8(&mut self, reader: &mut dyn BufRead) -> Result<DLDecoderResult, std::io::Error> {
+        let addr = reader.read_u24::<BigEndian>()? as usize;
+        let totalcnt = wrap256(reader.read_u8()?);
+        let mut value = 0;
+        let mut i = 0;
+        while i < totalcnt {
+            // raw span, followed by repeat count of its last pixel
+            let rawcnt = wrap256(reader.read_u8()?);
+            check_span(i + rawcnt, totalcnt)?;
+            for _ in 0..rawcnt {
+                value = reader.read_u8()?;
+                self.set8(addr + i, value);
+                i += 1;
+            }
+            if i < totalcnt {
+                let repeat = reader.read_u8()? as usize;
+                check_span(i + repeat, totalcnt)?;
+                for _ in 0..repeat {
+                    self.set8(addr + i, value);
+                    i += 1;
+                }
+            }
+        }
+        Ok(DLDecoderResult::Rlx(addr, totalcnt, false))
+    }
+
+    fn cmd_rlx16(&mut self, reader: &mut dyn BufRead) -> Result<DLDecoderResult, std::io::Error> {
+        let addr = reader.read_u24::<BigEndian>()? as usize;
+        let totalcnt = wrap256(reader.read_u8()?);
+        let mut value = 0;
+        let mut i = 0;
+        while i < totalcnt {
+            // raw span, followed by repeat count of its last pixel
+            let rawcnt = wrap256(reader.read_u8()?);
+            check_span(i + rawcnt, totalcnt)?;
+            for _ in 0..rawcnt {
+                value = reader.read_u16::<BigEndian>()?;
+                self.set16(addr + i * 2, value);
+                i += 1;
+            }
+            if i < totalcnt {
+                let repeat = reader.read_u8()? as usize;
+                check_span(i + repeat, totalcnt)?;
+                for _ in 0..repeat {
+                    self.set16(addr + i * 2, value);
+                    i += 1;
+                }
+            }
+        }
+        Ok(DLDecoderResult::Rlx(addr, totalcnt, true))
     }
 
     fn cmd_decomp8(&mut self, reader: &mut dyn BufRead) -> Result<DLDecoderResult, std::io::Error> {
@@ -215,7 +464,7 @@ impl DLDecoder {
                     break;
                 }
             }
-            self.gfxram[addr + i] = accumulator;
+            self.set8(addr + i, accumulator);
             tableidx = 0;
         }
         Ok(DLDecoderResult::Decomp(addr, cnt, false))
@@ -247,10 +496,108 @@ impl DLDecoder {
                     break;
                 }
             }
-            self.gfxram[addr + i * 2 + 1] = (accumulator >> 8) as u8;
-            self.gfxram[addr + i * 2] = (accumulator & 0xff) as u8;
+            self.set16(addr + i * 2, accumulator);
             tableidx = 8;
         }
         Ok(DLDecoderResult::Decomp(addr, cnt, true))
     }
+
+    // video RAM addresses wrap at 16 MiB
+    fn set8(&mut self, addr: usize, value: u8) {
+        self.gfxram[addr & 0xff_ffff] = value;
+    }
+
+    // 16-bit pixels are stored low byte first
+    fn set16(&mut self, addr: usize, value: u16) {
+        self.set8(addr, value as u8);
+        self.set8(addr + 1, (value >> 8) as u8);
+    }
+
+    fn copy(&mut self, dst: usize, src: usize, len: usize) {
+        if dst + len <= self.gfxram.len() && src + len <= self.gfxram.len() {
+            self.gfxram.copy_within(src..src + len, dst);
+        } else {
+            let data: Vec<u8> = (0..len)
+                .map(|i| self.gfxram[(src + i) & 0xff_ffff])
+                .collect();
+            for (i, value) in data.into_iter().enumerate() {
+                self.set8(dst + i, value);
+            }
+        }
+    }
+}
+
+/// Fails when a run of a command ends past its pixel count.
+fn check_span(end: usize, totalcnt: usize) -> std::io::Result<()> {
+    if end > totalcnt {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("run ends at pixel {} of {}", end, totalcnt),
+        ));
+    }
+    Ok(())
+}
+
+/// Passes reads through while keeping a copy of every byte consumed.
+struct Recorder<'a> {
+    inner: &'a mut dyn BufRead,
+    buf: &'a mut Vec<u8>,
+}
+
+impl Read for Recorder<'_> {
+    fn read(&mut self, out: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(out)?;
+        self.buf.extend_from_slice(&out[..n]);
+        Ok(n)
+    }
+}
+
+impl BufRead for Recorder<'_> {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        if let Ok(data) = self.inner.fill_buf() {
+            self.buf.extend_from_slice(&data[..amt.min(data.len())]);
+        }
+        self.inner.consume(amt);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, ErrorKind};
+
+    #[test]
+    fn writes_wrap_at_16_mib() {
+        let stream = [0xaf, 0x6b, 0xff, 0xff, 0xfe, 0x02, 0x01, 0x12, 0x34, 0x01];
+        let mut decoder = DLDecoder::default();
+        let result = decoder.parse_cmd(&mut Cursor::new(stream)).unwrap();
+        assert!(matches!(result, DLDecoderResult::Rlx(0xff_fffe, 2, true)));
+        let mut data = [0u8; 4];
+        decoder.dumpbuffer(&mut data, 0xff_fffe, 4);
+        assert_eq!(data, [0x34, 0x12, 0x34, 0x12]);
+    }
+
+    #[test]
+    fn repeat_past_span_is_rejected() {
+        let stream = [0xaf, 0x6b, 0x00, 0x00, 0x00, 0x02, 0x01, 0x12, 0x34, 0x40];
+        let mut decoder = DLDecoder::default();
+        let err = decoder.parse_cmd(&mut Cursor::new(stream)).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn bad_commands_are_errors() {
+        let mut decoder = DLDecoder::default();
+        let err = decoder
+            .parse_cmd(&mut Cursor::new([0xaf, 0x42]))
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        let err = decoder.parse_cmd(&mut Cursor::new([0xaf])).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
 }
@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+
+use super::{DLDecoder, DLDecoderResult};
+
+/// A command that wrote to `gfxram`.
+#[derive(Clone, Copy)]
+pub struct RamWrite {
+    /// Stream offset of the command.
+    pub offset: u64,
+    /// Number of commands parsed before it since tracking started.
+    pub index: u64,
+    pub result: DLDecoderResult,
+    // for memcpy, the write that had produced the copied byte
+    source: Option<u32>,
+}
+
+// writes kept before the first compaction
+const MIN_WRITES: usize = 1 << 18;
+
+/// Last write of every `gfxram` byte, as an index into `writes` where 0
+/// means nothing was recorded.
+pub struct Provenance {
+    last: Vec<u32>,
+    writes: Vec<RamWrite>,
+    commands: u64,
+    // compact once `writes` grows to this
+    limit: usize,
+}
+
+impl Provenance {
+    fn new(size: usize) -> Self {
+        let unknown = RamWrite {
+            offset: 0,
+            index: 0,
+            result: DLDecoderResult::Noop,
+            source: None,
+        };
+        Self {
+            last: vec![0; size],
+            writes: vec![unknown],
+            commands: 0,
+            limit: MIN_WRITES,
+        }
+    }
+
+    fn record(&mut self, offset: u64, result: &DLDecoderResult) {
+        let index = self.commands;
+        self.commands += 1;
+        let write = RamWrite {
+            offset,
+            index,
+            result: *result,
+            source: None,
+        };
+        match *result {
+            DLDecoderResult::Fill(addr, len, is16)
+            | DLDecoderResult::Decomp(addr, len, is16)
+            | DLDecoderResult::Raw(addr, len, is16)
+            | DLDecoderResult::Rlx(addr, len, is16) => {
+                let bpp = if is16 { 2 } else { 1 };
+                let id = self.writes.len() as u32;
+                self.writes.push(write);
+                for i in 0..len * bpp {
+                    self.last[(addr + i) & 0xff_ffff] = id;
+                }
+            }
+            DLDecoderResult::Memcpy(dst, len, is16, src) => {
+                let bpp = if is16 { 2 } else { 1 };
+                // one write per distinct source, so the chain can be followed
+                let mut ids = HashMap::new();
+                let sources: Vec<u32> = (0..len * bpp)
+                    .map(|i| self.last[(src + i) & 0xff_ffff])
+                    .collect();
+                for (i, source) in sources.into_iter().enumerate() {
+                    let id = *ids.entry(source).or_insert_with(|| {
+                        self.writes.push(RamWrite {
+                            source: Some(source),
+                            ..write
+                        });
+                        self.writes.len() as u32 - 1
+                    });
+                    self.last[(dst + i) & 0xff_ffff] = id;
+                }
+            }
+            _ => {}
+        }
+        if self.writes.len() >= self.limit {
+            self.compact();
+        }
+    }
+
+    /// Drops the writes no byte refers to anymore, directly or as the
+    /// source of a copy.
+    fn compact(&mut self) {
+        let mut keep = vec![false; self.writes.len()];
+        keep[0] = true;
+        for &id in &self.last {
+            keep[id as usize] = true;
+        }
+        // sources are older than the copies made from them
+        for id in (1..self.writes.len()).rev() {
+            if let (true, Some(source)) = (keep[id], self.writes[id].source) {
+                keep[source as usize] = true;
+            }
+        }
+        let mut remap = vec![0u32; self.writes.len()];
+        let mut writes = vec![];
+        for (id, write) in self.writes.iter().enumerate() {
+            if keep[id] {
+                remap[id] = writes.len() as u32;
+                writes.push(*write);
+            }
+        }
+        for write in &mut writes {
+            write.source = write.source.map(|source| remap[source as usize]);
+        }
+        for id in &mut self.last {
+            *id = remap[*id as usize];
+        }
+        self.writes = writes;
+        self.limit = (self.writes.len() * 2).max(MIN_WRITES);
+    }
+}
+
+impl DLDecoder {
+    /// Starts recording which command last wrote each `gfxram` byte. Bytes
+    /// written before have no recorded write.
+    pub fn track_writes(&mut self) {
+        if self.provenance.is_none() {
+            self.provenance = Some(Provenance::new(self.gfxram.len()));
+        }
+    }
+
+    pub(super) fn record_write(&mut self, result: &DLDecoderResult) {
+        let offset = self.offset;
+        if let Some(provenance) = &mut self.provenance {
+            provenance.record(offset, result);
+        }
+    }
+
+    /// Last write of the byte at `addr`, followed by the writes of the bytes
+    /// it was copied from for memcpy, with the address each one wrote.
+    /// `None` when writes are not tracked.
+    pub fn last_writes(&self, addr: usize) -> Option<Vec<(usize, RamWrite)>> {
+        let provenance = self.provenance.as_ref()?;
+        let mut chain = vec![];
+        let addr = addr & 0xff_ffff;
+        let (mut addr, mut id) = (addr, provenance.last[addr]);
+        while id != 0 {
+            let write = provenance.writes[id as usize];
+            chain.push((addr, write));
+            match (write.result, write.source) {
+                (DLDecoderResult::Memcpy(dst, _, _, src), Some(source)) => {
+                    addr = (addr + provenance.last.len() - dst + src) & 0xff_ffff;
+                    id = source;
+                }
+                _ => break,
+            }
+        }
+        Some(chain)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(decoder: &mut DLDecoder, offset: u64, result: DLDecoderResult) {
+        decoder.provenance.as_mut().unwrap().record(offset, &result);
+    }
+
+    #[test]
+    fn copies_survive_compaction() {
+        let mut decoder = DLDecoder::default();
+        decoder.track_writes();
+        record(&mut decoder, 1, DLDecoderResult::Raw(0x100, 4, false));
+        record(
+            &mut decoder,
+            2,
+            DLDecoderResult::Memcpy(0x200, 4, false, 0x100),
+        );
+        // overwrite the source, and keep rewriting one byte
+        record(&mut decoder, 3, DLDecoderResult::Fill(0x100, 4, false));
+        for offset in 4..(MIN_WRITES as u64 * 3) {
+            record(
+                &mut decoder,
+                offset,
+                DLDecoderResult::Fill(0x1000, 1, false),
+            );
+        }
+        assert!(decoder.provenance.as_ref().unwrap().writes.len() < MIN_WRITES);
+
+        let chain = decoder.last_writes(0x202).unwrap();
+        let offsets: Vec<_> = chain.iter().map(|(addr, w)| (*addr, w.offset)).collect();
+        assert_eq!(offsets, [(0x202, 2), (0x102, 1)]);
+        assert_eq!(decoder.last_writes(0x100).unwrap()[0].1.offset, 3);
+    }
+
+    #[test]
+    fn writes_wrap_at_16_mib() {
+        let mut decoder = DLDecoder::default();
+        decoder.track_writes();
+        record(&mut decoder, 1, DLDecoderResult::Raw(0xff_fffe, 4, false));
+        record(
+            &mut decoder,
+            2,
+            DLDecoderResult::Memcpy(0x10, 2, false, 0xff_ffff),
+        );
+        let chain = decoder.last_writes(0x11).unwrap();
+        let offsets: Vec<_> = chain.iter().map(|(addr, w)| (*addr, w.offset)).collect();
+        assert_eq!(offsets, [(0x11, 2), (0, 1)]);
+        assert!(decoder.last_writes(0x100_0001).unwrap()[0].1.offset == 1);
+    }
+}
@@ -0,0 +1,125 @@
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::io::prelude::*;
+use std::io::{Error, ErrorKind};
+
+use super::{DLDecoder, DecompNode};
+
+pub const SNAPSHOT_MAGIC: &[u8; 4] = b"DLSN";
+const SNAPSHOT_VERSION: u32 = 1;
+
+// zero runs shorter than this stay inside a data run
+const MIN_ZERO_RUN: usize = 16;
+
+impl DLDecoder {
+    /// Writes the decoder state as a snapshot:
+    ///
+    /// - magic `DLSN`, u32 version, u64 next stream offset, u64 frame count
+    /// - 256 registers, 512 decompression table entries of 9 bytes
+    /// - `gfxram` as (u32 zero count, u32 data length, data) runs until
+    ///   16 MiB are covered
+    ///
+    /// All integers are big endian.
+    pub fn write_snapshot(&self, writer: &mut dyn Write) -> std::io::Result<()> {
+        self.write_state(writer, None)
+    }
+
+    /// Writes a snapshot whose runs skip the bytes equal to those in
+    /// `base` rather than the zero bytes, read back with `read_delta`.
+    pub fn write_delta(&self, base: &DLDecoder, writer: &mut dyn Write) -> std::io::Result<()> {
+        self.write_state(writer, Some(&base.gfxram))
+    }
+
+    fn write_state(&self, writer: &mut dyn Write, base: Option<&[u8]>) -> std::io::Result<()> {
+        writer.write_all(SNAPSHOT_MAGIC)?;
+        writer.write_u32::<BigEndian>(SNAPSHOT_VERSION)?;
+        writer.write_u64::<BigEndian>(self.get_next_offset())?;
+        writer.write_u64::<BigEndian>(self.frames)?;
+        writer.write_all(&self.reg)?;
+        let mut nodebuf = [0u8; 9];
+        for nodes in &self.decomp_table {
+            DecompNode::write_to(nodes, &mut nodebuf);
+            writer.write_all(&nodebuf)?;
+        }
+
+        let ram = &self.gfxram;
+        let same = |from: usize| match base {
+            Some(base) => (from..ram.len()).take_while(|&i| ram[i] == base[i]).count(),
+            None => ram[from..].iter().take_while(|&&b| b == 0).count(),
+        };
+        let mut pos = 0;
+        while pos < ram.len() {
+            let zeros = same(pos);
+            let start = pos + zeros;
+            let mut end = start;
+            while end < ram.len() {
+                let run = same(end);
+                if run >= MIN_ZERO_RUN || end + run == ram.len() {
+                    break;
+                }
+                end += run.max(1);
+            }
+            writer.write_u32::<BigEndian>(zeros as u32)?;
+            writer.write_u32::<BigEndian>((end - start) as u32)?;
+            writer.write_all(&ram[start..end])?;
+            pos = end;
+        }
+        Ok(())
+    }
+
+    /// Restores the state written by `write_snapshot`.
+    pub fn read_snapshot(reader: &mut dyn Read) -> std::io::Result<Self> {
+        Self::read_state(reader, None)
+    }
+
+    /// Restores the state written by `write_delta` against `base`.
+    pub fn read_delta(base: &DLDecoder, reader: &mut dyn Read) -> std::io::Result<Self> {
+        Self::read_state(reader, Some(&base.gfxram))
+    }
+
+    fn read_state(reader: &mut dyn Read, base: Option<&[u8]>) -> std::io::Result<Self> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != SNAPSHOT_MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "not a snapshot"));
+        }
+        let version = reader.read_u32::<BigEndian>()?;
+        if version != SNAPSHOT_VERSION {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("unsupported snapshot version {}", version),
+            ));
+        }
+        let mut decoder = Self::default();
+        if let Some(base) = base {
+            decoder.gfxram.copy_from_slice(base);
+        }
+        decoder.set_next_offset(reader.read_u64::<BigEndian>()?);
+        decoder.frames = reader.read_u64::<BigEndian>()?;
+        reader.read_exact(&mut decoder.reg)?;
+        let mut nodebuf = [0u8; 9];
+        for nodes in decoder.decomp_table.iter_mut() {
+            reader.read_exact(&mut nodebuf)?;
+            *nodes = DecompNode::read_from(&nodebuf);
+        }
+
+        if decoder.frames > 0 {
+            decoder.front = Some((
+                decoder.get_current_address_16(),
+                decoder.get_current_address_8(),
+            ));
+        }
+
+        let mut pos = 0;
+        while pos < decoder.gfxram.len() {
+            let zeros = reader.read_u32::<BigEndian>()? as usize;
+            let len = reader.read_u32::<BigEndian>()? as usize;
+            let start = pos + zeros;
+            if start + len > decoder.gfxram.len() || zeros + len == 0 {
+                return Err(Error::new(ErrorKind::InvalidData, "bad gfxram run"));
+            }
+            reader.read_exact(&mut decoder.gfxram[start..start + len])?;
+            pos = start + len;
+        }
+        Ok(decoder)
+    }
+}
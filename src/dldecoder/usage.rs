@@ -0,0 +1,145 @@
+use std::ops::Range;
+
+use super::{DLDecoder, DLDecoderResult};
+
+/// Bytes of `gfxram` covered by one entry of the usage map.
+pub const BLOCK_SIZE: usize = 256;
+const BLOCKS: usize = 0x100_0000 / BLOCK_SIZE;
+
+const WRITTEN: u8 = 1;
+const COPY_SOURCE: u8 = 2;
+const FRAMEBUFFER: u8 = 4;
+const PLANE8: u8 = 8;
+// touched since the last commit, and between the last two commits
+const THIS_FRAME: u8 = 16;
+const LAST_FRAME: u8 = 32;
+
+/// What a block of `gfxram` has been used for, most significant first.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Region {
+    /// Part of the 16-bit framebuffer set in the registers.
+    Framebuffer16,
+    /// Part of the 8-bit plane set in the registers.
+    Plane8,
+    /// Shown at an earlier commit, but not now.
+    OldFramebuffer,
+    /// Read by memcpy.
+    CopySource,
+    Written,
+    Unused,
+}
+
+/// Usage flags of every block since tracking started.
+#[derive(Clone)]
+pub struct Usage {
+    blocks: Vec<u8>,
+}
+
+impl Usage {
+    fn mark(&mut self, range: Range<usize>, flags: u8) {
+        let last = (range.end - 1) / BLOCK_SIZE;
+        for block in range.start / BLOCK_SIZE..=last {
+            self.blocks[block % BLOCKS] |= flags;
+        }
+    }
+
+    fn record(&mut self, result: &DLDecoderResult) {
+        match *result {
+            DLDecoderResult::Fill(addr, len, is16)
+            | DLDecoderResult::Decomp(addr, len, is16)
+            | DLDecoderResult::Raw(addr, len, is16)
+            | DLDecoderResult::Rlx(addr, len, is16) => {
+                let bpp = if is16 { 2 } else { 1 };
+                self.mark(addr..addr + len * bpp, WRITTEN | THIS_FRAME);
+            }
+            DLDecoderResult::Memcpy(dst, len, is16, src) => {
+                let bpp = if is16 { 2 } else { 1 };
+                self.mark(dst..dst + len * bpp, WRITTEN | THIS_FRAME);
+                self.mark(src..src + len * bpp, COPY_SOURCE | THIS_FRAME);
+            }
+            _ => {}
+        }
+    }
+
+    fn commit(&mut self, framebuffer: Range<usize>, plane8: Range<usize>) {
+        if !framebuffer.is_empty() {
+            self.mark(framebuffer, FRAMEBUFFER);
+            self.mark(plane8, PLANE8);
+        }
+        for flags in self.blocks.iter_mut() {
+            *flags &= !LAST_FRAME;
+            if *flags & THIS_FRAME != 0 {
+                *flags = (*flags & !THIS_FRAME) | LAST_FRAME;
+            }
+        }
+    }
+}
+
+impl DLDecoder {
+    /// Starts recording what each block of `gfxram` is used for.
+    pub fn track_usage(&mut self) {
+        if self.usage.is_none() {
+            self.usage = Some(Usage {
+                blocks: vec![0; BLOCKS],
+            });
+        }
+    }
+
+    fn framebuffer(&self) -> (Range<usize>, Range<usize>) {
+        let len = self.get_width() * self.get_height();
+        let addr16 = self.get_current_address_16();
+        let addr8 = self.get_current_address_8();
+        (addr16..addr16 + len * 2, addr8..addr8 + len)
+    }
+
+    pub(super) fn record_usage(&mut self, result: &DLDecoderResult) {
+        let commit = matches!(result, DLDecoderResult::Setreg(0xff, 0xff)) && self.reg[0x1f] == 0;
+        let (framebuffer, plane8) = self.framebuffer();
+        if let Some(usage) = &mut self.usage {
+            usage.record(result);
+            if commit {
+                usage.commit(framebuffer, plane8);
+            }
+        }
+    }
+
+    /// Region of every `BLOCK_SIZE` block and whether it was written or
+    /// copied from in the last complete frame. `None` when usage is not
+    /// tracked.
+    pub fn usage_map(&self) -> Option<Vec<(Region, bool)>> {
+        let usage = self.usage.as_ref()?;
+        let (framebuffer, plane8) = self.framebuffer();
+        let block_range = |r: Range<usize>| {
+            if r.is_empty() {
+                0..0
+            } else {
+                r.start / BLOCK_SIZE..(r.end - 1) / BLOCK_SIZE + 1
+            }
+        };
+        let (framebuffer, plane8) = (block_range(framebuffer), block_range(plane8));
+        let contains =
+            |r: &Range<usize>, block: usize| r.contains(&block) || r.contains(&(block + BLOCKS));
+        let map = usage
+            .blocks
+            .iter()
+            .enumerate()
+            .map(|(block, &flags)| {
+                let region = if contains(&framebuffer, block) {
+                    Region::Framebuffer16
+                } else if contains(&plane8, block) {
+                    Region::Plane8
+                } else if flags & (FRAMEBUFFER | PLANE8) != 0 {
+                    Region::OldFramebuffer
+                } else if flags & COPY_SOURCE != 0 {
+                    Region::CopySource
+                } else if flags & WRITTEN != 0 {
+                    Region::Written
+                } else {
+                    Region::Unused
+                };
+                (region, flags & LAST_FRAME != 0)
+            })
+            .collect();
+        Some(map)
+    }
+}
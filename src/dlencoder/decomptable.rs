@@ -0,0 +1,415 @@
+use byteorder::{BigEndian, WriteBytesExt};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::io::prelude::*;
+
+use crate::dldecoder::DecompNode;
+
+const TABLE_SIZE: usize = 512;
+// decoder starts walking the table from these entries
+const ROOT8: usize = 0;
+const ROOT16: usize = 8;
+// leave room for both trees and their escape chains in 512 entries
+const MAX_CODES8: usize = 224;
+const MAX_CODES16: usize = 256;
+
+const LOAD_MAGIC: [u8; 4] = [0x26, 0x38, 0x71, 0xcd];
+
+/// Histogram of pixel deltas as seen by the decompressor.
+///
+/// The accumulator is reset at every command, so spans are split at 256
+/// pixels exactly like the encoder does.
+#[derive(Default)]
+pub struct DeltaStats {
+    counts: HashMap<u16, u64>,
+}
+
+impl DeltaStats {
+    pub fn add_span16(&mut self, pixels: &[u16]) {
+        for chunk in pixels.chunks(256) {
+            let mut acc = 0u16;
+            for &p in chunk {
+                *self.counts.entry(p.wrapping_sub(acc)).or_default() += 1;
+                acc = p;
+            }
+        }
+    }
+
+    pub fn add_span8(&mut self, pixels: &[u8]) {
+        for chunk in pixels.chunks(256) {
+            let mut acc = 0u8;
+            for &p in chunk {
+                *self.counts.entry(p.wrapping_sub(acc) as u16).or_default() += 1;
+                acc = p;
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct Code {
+    // in emission order, first bit at LSB
+    bits: u64,
+    len: u32,
+}
+
+enum Symbol {
+    Delta(u16),
+    Escape,
+}
+
+enum Tree {
+    Leaf(Symbol),
+    Node(Box<Tree>, Box<Tree>),
+}
+
+struct Codebook {
+    width: u32,
+    codes: HashMap<u16, Code>,
+    escape: Code,
+}
+
+impl Codebook {
+    fn put(&self, bits: &mut BitWriter, delta: u16) {
+        match self.codes.get(&delta) {
+            Some(code) => bits.put(code.bits, code.len),
+            None => {
+                bits.put(self.escape.bits, self.escape.len);
+                bits.put(delta as u64, self.width);
+            }
+        }
+    }
+}
+
+struct BitWriter {
+    buf: Vec<u8>,
+    cnt: u32,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        Self {
+            buf: vec![],
+            cnt: 0,
+        }
+    }
+
+    fn put(&mut self, bits: u64, len: u32) {
+        for i in 0..len {
+            if self.cnt.is_multiple_of(8) {
+                self.buf.push(0);
+            }
+            if (bits >> i) & 1 != 0 {
+                *self.buf.last_mut().unwrap() |= 1 << (self.cnt % 8);
+            }
+            self.cnt += 1;
+        }
+    }
+}
+
+/// Decompression table for the 0x70/0x78 commands.
+///
+/// Each plane gets a huffman tree over its most frequent deltas. Deltas
+/// without a code are sent as an escape code followed by the raw delta,
+/// using a chain of nodes that add one bit each.
+pub struct DecompTable {
+    nodes: Vec<[DecompNode; 2]>,
+    used: usize,
+    book8: Codebook,
+    book16: Codebook,
+}
+
+impl DecompTable {
+    pub fn build(stats16: &DeltaStats, stats8: &DeltaStats) -> Self {
+        let mut table = Self {
+            nodes: vec![[DecompNode { color: 0, next: 0 }; 2]; TABLE_SIZE],
+            used: ROOT16 + 1,
+            book8: Codebook {
+                width: 8,
+                codes: HashMap::new(),
+                escape: Code::default(),
+            },
+            book16: Codebook {
+                width: 16,
+                codes: HashMap::new(),
+                escape: Code::default(),
+            },
+        };
+        let mut free = (1..TABLE_SIZE).filter(|&i| i != ROOT16);
+        table.book16 = table.place(build_tree(stats16, MAX_CODES16), ROOT16, 16, &mut free);
+        table.book8 = table.place(build_tree(stats8, MAX_CODES8), ROOT8, 8, &mut free);
+        table
+    }
+
+    pub fn nodes(&self) -> &[[DecompNode; 2]] {
+        &self.nodes[..self.used]
+    }
+
+    pub fn write_load_cmd(&self, writer: &mut dyn Write) -> Result<(), std::io::Error> {
+        writer.write_all(&[0xaf, 0xe0])?;
+        writer.write_all(&LOAD_MAGIC)?;
+        writer.write_u32::<BigEndian>(self.used as u32)?;
+        let mut nodebuf = [0u8; 9];
+        for pair in self.nodes() {
+            DecompNode::write_to(pair, &mut nodebuf);
+            writer.write_all(&nodebuf)?;
+        }
+        Ok(())
+    }
+
+    /// Writes `pixels` starting at `addr` as 0x78 commands of up to 256 pixels.
+    pub fn write_decomp16(
+        &self,
+        writer: &mut dyn Write,
+        addr: usize,
+        pixels: &[u16],
+    ) -> Result<(), std::io::Error> {
+        let mut addr = addr;
+        for chunk in pixels.chunks(256) {
+            let mut bits = BitWriter::new();
+            let mut acc = 0u16;
+            for &p in chunk {
+                self.book16.put(&mut bits, p.wrapping_sub(acc));
+                acc = p;
+            }
+            writer.write_all(&[0xaf, 0x78])?;
+            writer.write_u24::<BigEndian>(addr as u32)?;
+            writer.write_u8(chunk.len() as u8)?;
+            writer.write_all(&bits.buf)?;
+            addr += chunk.len() * 2;
+        }
+        Ok(())
+    }
+
+    /// Writes `pixels` starting at `addr` as 0x70 commands of up to 256 pixels.
+    pub fn write_decomp8(
+        &self,
+        writer: &mut dyn Write,
+        addr: usize,
+        pixels: &[u8],
+    ) -> Result<(), std::io::Error> {
+        let mut addr = addr;
+        for chunk in pixels.chunks(256) {
+            let mut bits = BitWriter::new();
+            let mut acc = 0u8;
+            for &p in chunk {
+                self.book8.put(&mut bits, p.wrapping_sub(acc) as u16);
+                acc = p;
+            }
+            writer.write_all(&[0xaf, 0x70])?;
+            writer.write_u24::<BigEndian>(addr as u32)?;
+            writer.write_u8(chunk.len() as u8)?;
+            writer.write_all(&bits.buf)?;
+            addr += chunk.len();
+        }
+        Ok(())
+    }
+
+    fn alloc(&mut self, free: &mut dyn Iterator<Item = usize>) -> usize {
+        let idx = free.next().expect("decompression table overflow");
+        self.used = self.used.max(idx + 1);
+        idx
+    }
+
+    fn place(
+        &mut self,
+        tree: Tree,
+        root: usize,
+        width: u32,
+        free: &mut dyn Iterator<Item = usize>,
+    ) -> Codebook {
+        // escape chain, one node per delta bit
+        let chain: Vec<usize> = (0..width).map(|_| self.alloc(free)).collect();
+        for (k, &idx) in chain.iter().enumerate() {
+            let next = chain.get(k + 1).copied().unwrap_or(0);
+            self.nodes[idx] = [
+                DecompNode { color: 0, next },
+                DecompNode {
+                    color: 1 << k,
+                    next,
+                },
+            ];
+        }
+
+        let mut book = Codebook {
+            width,
+            codes: HashMap::new(),
+            escape: Code::default(),
+        };
+        let mut pending = vec![(tree, root, Code::default())];
+        while let Some((tree, idx, code)) = pending.pop() {
+            let Tree::Node(zero, one) = tree else {
+                unreachable!()
+            };
+            for (bit, child) in [(0, *zero), (1, *one)] {
+                let code = Code {
+                    bits: code.bits | (bit as u64) << code.len,
+                    len: code.len + 1,
+                };
+                self.nodes[idx][bit] = match child {
+                    Tree::Leaf(Symbol::Delta(delta)) => {
+                        book.codes.insert(delta, code);
+                        DecompNode {
+                            color: delta,
+                            next: 0,
+                        }
+                    }
+                    Tree::Leaf(Symbol::Escape) => {
+                        book.escape = code;
+                        DecompNode {
+                            color: 0,
+                            next: chain[0],
+                        }
+                    }
+                    node => {
+                        let next = self.alloc(free);
+                        pending.push((node, next, code));
+                        DecompNode { color: 0, next }
+                    }
+                };
+            }
+        }
+        book
+    }
+}
+
+fn build_tree(stats: &DeltaStats, max_codes: usize) -> Tree {
+    let mut deltas: Vec<(u64, u16)> = stats.counts.iter().map(|(&d, &c)| (c, d)).collect();
+    deltas.sort_by_key(|&(c, d)| (Reverse(c), d));
+    let escape_weight: u64 = deltas.iter().skip(max_codes).map(|&(c, _)| c).sum();
+    deltas.truncate(max_codes);
+    if deltas.is_empty() {
+        // huffman needs two symbols
+        deltas.push((0, 0));
+    }
+
+    // scale weights down so code lengths stay well within 64 bits
+    let total: u64 = deltas.iter().map(|&(c, _)| c).sum::<u64>() + escape_weight;
+    let shift = (64 - total.leading_zeros()).saturating_sub(32);
+
+    let mut trees: Vec<Option<Tree>> = vec![];
+    let mut heap = BinaryHeap::new();
+    let mut push = |trees: &mut Vec<Option<Tree>>, weight: u64, tree: Tree| {
+        heap.push(Reverse((weight, trees.len())));
+        trees.push(Some(tree));
+    };
+    for &(c, d) in &deltas {
+        push(
+            &mut trees,
+            (c >> shift).max(1),
+            Tree::Leaf(Symbol::Delta(d)),
+        );
+    }
+    push(
+        &mut trees,
+        (escape_weight >> shift).max(1),
+        Tree::Leaf(Symbol::Escape),
+    );
+    loop {
+        let Reverse((wa, a)) = heap.pop().unwrap();
+        let Some(Reverse((wb, b))) = heap.pop() else {
+            return trees[a].take().unwrap();
+        };
+        let node = Tree::Node(
+            Box::new(trees[a].take().unwrap()),
+            Box::new(trees[b].take().unwrap()),
+        );
+        heap.push(Reverse((wa + wb, trees.len())));
+        trees.push(Some(node));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dldecoder::DLDecoder;
+    use std::io::Cursor;
+
+    const ADDR16: usize = 0x1000;
+    const ADDR8: usize = 0x80_0000;
+
+    /// Runs with repeated deltas the table codes, broken up by values it
+    /// has to escape.
+    fn pixels(len: usize) -> Vec<u16> {
+        let mut seed = 7u32;
+        (0..len)
+            .map(|i| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                match i % 40 {
+                    0..20 => (i / 40) as u16 * 0x0841,
+                    20..30 => (i % 40) as u16 * 3,
+                    _ => (seed >> 16) as u16,
+                }
+            })
+            .collect()
+    }
+
+    fn decode(stream: Vec<u8>) -> DLDecoder {
+        let mut reader = Cursor::new(stream);
+        let mut decoder = DLDecoder::default();
+        while decoder.parse_cmd(&mut reader).is_ok() {}
+        assert_eq!(reader.position(), reader.get_ref().len() as u64);
+        decoder
+    }
+
+    fn round_trip(table: &DecompTable, len: usize) {
+        let pixels16 = pixels(len);
+        let pixels8: Vec<u8> = pixels16.iter().map(|&p| (p ^ p >> 8) as u8).collect();
+        let mut stream = vec![];
+        table.write_load_cmd(&mut stream).unwrap();
+        table
+            .write_decomp16(&mut stream, ADDR16, &pixels16)
+            .unwrap();
+        table.write_decomp8(&mut stream, ADDR8, &pixels8).unwrap();
+        let decoder = decode(stream);
+
+        let mut data16 = vec![0u8; len * 2];
+        decoder.dumpbuffer(&mut data16, ADDR16, len * 2);
+        let decoded16: Vec<u16> = data16
+            .chunks(2)
+            .map(|d| d[0] as u16 | (d[1] as u16) << 8)
+            .collect();
+        assert!(decoded16 == pixels16, "16-bit run of {} pixels", len);
+        let mut data8 = vec![0u8; len];
+        decoder.dumpbuffer(&mut data8, ADDR8, len);
+        assert!(data8 == pixels8, "8-bit run of {} pixels", len);
+    }
+
+    fn table() -> DecompTable {
+        // only the first part is known, so the rest needs escapes
+        let pixels16 = pixels(400);
+        let pixels8: Vec<u8> = pixels16.iter().map(|&p| (p ^ p >> 8) as u8).collect();
+        let mut stats16 = DeltaStats::default();
+        let mut stats8 = DeltaStats::default();
+        stats16.add_span16(&pixels16[..100]);
+        stats8.add_span8(&pixels8[..100]);
+        DecompTable::build(&stats16, &stats8)
+    }
+
+    #[test]
+    fn runs_decode() {
+        let table = table();
+        for len in [1, 39, 255, 256, 257, 512, 700] {
+            round_trip(&table, len);
+        }
+    }
+
+    #[test]
+    fn empty_table_escapes_everything() {
+        let table = DecompTable::build(&DeltaStats::default(), &DeltaStats::default());
+        round_trip(&table, 256);
+        round_trip(&table, 300);
+    }
+
+    #[test]
+    fn full_table_fits() {
+        let mut stats16 = DeltaStats::default();
+        let mut stats8 = DeltaStats::default();
+        let all: Vec<u16> = (0..=u16::MAX).map(|p| p.wrapping_mul(40503)).collect();
+        stats16.add_span16(&all);
+        stats8.add_span8(&all.iter().map(|&p| p as u8).collect::<Vec<_>>());
+        let table = DecompTable::build(&stats16, &stats8);
+        assert!(table.nodes().len() <= TABLE_SIZE);
+        round_trip(&table, 600);
+    }
+}
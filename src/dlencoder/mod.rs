@@ -0,0 +1,513 @@
+use byteorder::{BigEndian, WriteBytesExt};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::io::prelude::*;
+
+mod decomptable;
+pub use decomptable::{DecompTable, DeltaStats};
+
+// unchanged pixels cheaper to resend than to start a new command
+const MERGE_GAP: usize = 8;
+const MAX_SCROLL: usize = 64;
+
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Strategy {
+    Raw,
+    Fill,
+    Rlx,
+    Decomp,
+    Best,
+}
+
+/// Splits RGB888 pixels into the RGB565 plane and the 8-bit plane holding
+/// the remaining low bits.
+pub fn rgb_to_planes(rgb: &[u8]) -> (Vec<u16>, Vec<u8>) {
+    rgb.chunks_exact(3)
+        .map(|p| {
+            let (r, g, b) = (p[0], p[1], p[2]);
+            let h = (r as u16 >> 3) << 11 | (g as u16 >> 2) << 5 | b as u16 >> 3;
+            let l = (r & 7) << 5 | (g & 3) << 3 | b & 7;
+            (h, l)
+        })
+        .unzip()
+}
+
+struct Plane {
+    bpp: usize,
+    base: usize,
+    // what the decoder holds after our commands so far
+    pixels: Vec<u16>,
+}
+
+pub struct DLEncoder {
+    strategy: Strategy,
+    memcpy: bool,
+    depth24: bool,
+    table: Option<DecompTable>,
+    table_sent: bool,
+    size: (usize, usize),
+    planes: [Plane; 2],
+    model_valid: bool,
+}
+
+impl DLEncoder {
+    pub fn new(strategy: Strategy, memcpy: bool, depth24: bool) -> Self {
+        Self {
+            strategy,
+            memcpy,
+            depth24,
+            table: None,
+            table_sent: false,
+            size: (0, 0),
+            planes: [
+                Plane {
+                    bpp: 2,
+                    base: 0,
+                    pixels: vec![],
+                },
+                Plane {
+                    bpp: 1,
+                    base: 0,
+                    pixels: vec![],
+                },
+            ],
+            model_valid: false,
+        }
+    }
+
+    pub fn set_table(&mut self, table: DecompTable) {
+        self.table = Some(table);
+        self.table_sent = false;
+    }
+
+    /// Writes one RGB888 frame followed by an unlock, setting the mode first
+    /// if the size changed.
+    pub fn write_frame(
+        &mut self,
+        writer: &mut dyn Write,
+        width: usize,
+        height: usize,
+        rgb: &[u8],
+    ) -> Result<(), std::io::Error> {
+        if (width, height) != self.size {
+            self.write_modeset(writer, width, height)?;
+        }
+        if let (Some(table), false) = (&self.table, self.table_sent) {
+            table.write_load_cmd(writer)?;
+            self.table_sent = true;
+        }
+        let (plane16, plane8) = rgb_to_planes(rgb);
+        self.write_plane(writer, 0, &plane16)?;
+        if self.depth24 {
+            let plane8: Vec<u16> = plane8.into_iter().map(u16::from).collect();
+            self.write_plane(writer, 1, &plane8)?;
+        }
+        self.model_valid = true;
+        write_setreg(writer, 0xff, 0xff)
+    }
+
+    fn write_modeset(
+        &mut self,
+        writer: &mut dyn Write,
+        width: usize,
+        height: usize,
+    ) -> Result<(), std::io::Error> {
+        let base16 = 0;
+        let base8 = width * height * 2;
+        let regs = [
+            (0xff, 0x00),
+            (0x00, self.depth24 as u8),
+            (0x0f, (width >> 8) as u8),
+            (0x10, width as u8),
+            (0x17, (height >> 8) as u8),
+            (0x18, height as u8),
+            (0x20, (base16 >> 16) as u8),
+            (0x21, (base16 >> 8) as u8),
+            (0x22, base16 as u8),
+            (0x26, (base8 >> 16) as u8),
+            (0x27, (base8 >> 8) as u8),
+            (0x28, base8 as u8),
+            (0x1f, 0x00),
+        ];
+        for (addr, val) in regs {
+            write_setreg(writer, addr, val)?;
+        }
+        self.size = (width, height);
+        for (plane, base) in self.planes.iter_mut().zip([base16, base8]) {
+            plane.base = base;
+            plane.pixels = vec![0; width * height];
+        }
+        self.model_valid = false;
+        Ok(())
+    }
+
+    fn write_plane(
+        &mut self,
+        writer: &mut dyn Write,
+        idx: usize,
+        new: &[u16],
+    ) -> Result<(), std::io::Error> {
+        let (width, height) = self.size;
+        let plane = &mut self.planes[idx];
+        let model_valid = self.model_valid;
+        let scroll = if self.memcpy && model_valid {
+            detect_scroll(&plane.pixels, new, width)
+        } else {
+            0
+        };
+        // keep memcpy sources untouched until they are copied
+        let rows: Vec<usize> = if scroll < 0 {
+            (0..height).rev().collect()
+        } else {
+            (0..height).collect()
+        };
+
+        let mut prev_row = None;
+        let mut cmd = vec![];
+        let mut best = vec![];
+        for y in rows {
+            let row = &new[y * width..(y + 1) * width];
+            let old = &plane.pixels[y * width..(y + 1) * width];
+            for (x0, x1) in damage(model_valid.then_some(old), row) {
+                for start in (x0..x1).step_by(256) {
+                    let px = &row[start..x1.min(start + 256)];
+                    let pos = y * width + start;
+                    let addr = plane.base + pos * plane.bpp;
+                    best.clear();
+
+                    if self.memcpy {
+                        let srcrows = [y.checked_add_signed(scroll), prev_row];
+                        for sy in srcrows.into_iter().flatten() {
+                            if sy >= height || sy == y {
+                                continue;
+                            }
+                            let src = sy * width + start;
+                            if plane.pixels[src..src + px.len()] == *px {
+                                let srcaddr = plane.base + src * plane.bpp;
+                                write_memcpy(&mut best, plane.bpp, addr, srcaddr, px.len())?;
+                                break;
+                            }
+                        }
+                    }
+
+                    let kinds = match self.strategy {
+                        Strategy::Best => &[
+                            Strategy::Fill,
+                            Strategy::Rlx,
+                            Strategy::Decomp,
+                            Strategy::Raw,
+                        ][..],
+                        ref kind => std::slice::from_ref(kind),
+                    };
+                    for kind in kinds {
+                        cmd.clear();
+                        match (kind, &self.table) {
+                            (Strategy::Fill, _) => write_fill(&mut cmd, plane.bpp, addr, px)?,
+                            (Strategy::Rlx, _) => write_rlx(&mut cmd, plane.bpp, addr, px)?,
+                            (Strategy::Decomp, Some(table)) if plane.bpp == 2 => {
+                                table.write_decomp16(&mut cmd, addr, px)?
+                            }
+                            (Strategy::Decomp, Some(table)) => {
+                                let px: Vec<u8> = px.iter().map(|&p| p as u8).collect();
+                                table.write_decomp8(&mut cmd, addr, &px)?
+                            }
+                            _ => write_raw(&mut cmd, plane.bpp, addr, px)?,
+                        }
+                        if best.is_empty() || cmd.len() < best.len() {
+                            std::mem::swap(&mut cmd, &mut best);
+                        }
+                    }
+
+                    writer.write_all(&best)?;
+                    plane.pixels[pos..pos + px.len()].copy_from_slice(px);
+                }
+            }
+            prev_row = Some(y);
+        }
+        Ok(())
+    }
+}
+
+fn write_setreg(writer: &mut dyn Write, addr: u8, val: u8) -> Result<(), std::io::Error> {
+    writer.write_all(&[0xaf, 0x20, addr, val])
+}
+
+fn write_header(
+    writer: &mut dyn Write,
+    bpp: usize,
+    cmd: u8,
+    addr: usize,
+    cnt: usize,
+) -> Result<(), std::io::Error> {
+    let cmd = if bpp == 2 { cmd | 0x08 } else { cmd };
+    writer.write_all(&[0xaf, cmd])?;
+    writer.write_u24::<BigEndian>(addr as u32)?;
+    // 256 wraps to 0
+    writer.write_u8(cnt as u8)
+}
+
+fn write_pixel(writer: &mut dyn Write, bpp: usize, value: u16) -> Result<(), std::io::Error> {
+    if bpp == 2 {
+        writer.write_u16::<BigEndian>(value)
+    } else {
+        writer.write_u8(value as u8)
+    }
+}
+
+fn write_raw(
+    writer: &mut dyn Write,
+    bpp: usize,
+    addr: usize,
+    px: &[u16],
+) -> Result<(), std::io::Error> {
+    write_header(writer, bpp, 0x60, addr, px.len())?;
+    for &p in px {
+        write_pixel(writer, bpp, p)?;
+    }
+    Ok(())
+}
+
+fn write_fill(
+    writer: &mut dyn Write,
+    bpp: usize,
+    addr: usize,
+    px: &[u16],
+) -> Result<(), std::io::Error> {
+    write_header(writer, bpp, 0x61, addr, px.len())?;
+    for run in px.chunk_by(|a, b| a == b) {
+        writer.write_u8(run.len() as u8)?;
+        write_pixel(writer, bpp, run[0])?;
+    }
+    Ok(())
+}
+
+fn write_memcpy(
+    writer: &mut dyn Write,
+    bpp: usize,
+    addr: usize,
+    srcaddr: usize,
+    cnt: usize,
+) -> Result<(), std::io::Error> {
+    write_header(writer, bpp, 0x62, addr, cnt)?;
+    writer.write_u24::<BigEndian>(srcaddr as u32)
+}
+
+fn write_rlx(
+    writer: &mut dyn Write,
+    bpp: usize,
+    addr: usize,
+    px: &[u16],
+) -> Result<(), std::io::Error> {
+    write_header(writer, bpp, 0x63, addr, px.len())?;
+    let mut i = 0;
+    while i < px.len() {
+        // raw span up to the first pixel that repeats
+        let start = i;
+        while i + 1 < px.len() && px[i + 1] != px[i] {
+            i += 1;
+        }
+        i += 1;
+        writer.write_u8((i - start) as u8)?;
+        for &p in &px[start..i] {
+            write_pixel(writer, bpp, p)?;
+        }
+        if i < px.len() {
+            let repeat = px[i..].iter().take_while(|&&p| p == px[i - 1]).count();
+            writer.write_u8(repeat as u8)?;
+            i += repeat;
+        }
+    }
+    Ok(())
+}
+
+/// Changed spans of a row, merging spans separated by short gaps.
+fn damage(old: Option<&[u16]>, new: &[u16]) -> Vec<(usize, usize)> {
+    let Some(old) = old else {
+        return vec![(0, new.len())];
+    };
+    let mut spans = vec![];
+    let mut x = 0;
+    while x < new.len() {
+        if old[x] == new[x] {
+            x += 1;
+            continue;
+        }
+        let start = x;
+        x += 1;
+        let mut end = x;
+        while x < new.len() && x - end < MERGE_GAP {
+            if old[x] != new[x] {
+                end = x + 1;
+            }
+            x += 1;
+        }
+        spans.push((start, end));
+    }
+    spans
+}
+
+/// Guesses a vertical scroll by matching row hashes, returning the offset
+/// from a new row to its old position.
+fn detect_scroll(old: &[u16], new: &[u16], width: usize) -> isize {
+    let row_hash = |row: &[u16]| {
+        let mut hasher = DefaultHasher::new();
+        row.hash(&mut hasher);
+        hasher.finish()
+    };
+    let mut old_rows: HashMap<u64, Vec<usize>> = HashMap::new();
+    for (y, row) in old.chunks(width).enumerate() {
+        old_rows.entry(row_hash(row)).or_default().push(y);
+    }
+
+    let mut votes: HashMap<isize, usize> = HashMap::new();
+    for (y, row) in new.chunks(width).enumerate() {
+        if *row == old[y * width..(y + 1) * width] {
+            continue;
+        }
+        let Some(candidates) = old_rows.get(&row_hash(row)) else {
+            continue;
+        };
+        // flat rows match everywhere and say nothing about scrolling
+        if candidates.len() > 4 {
+            continue;
+        }
+        for &oy in candidates {
+            if oy.abs_diff(y) <= MAX_SCROLL {
+                *votes.entry(oy as isize - y as isize).or_default() += 1;
+            }
+        }
+    }
+    votes
+        .into_iter()
+        // ties go to the smaller shift, then the positive one, whatever the map order
+        .max_by_key(|&(dy, cnt)| (cnt, -dy.abs(), dy))
+        .filter(|&(_, cnt)| cnt >= 4)
+        .map_or(0, |(dy, _)| dy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dldecoder::{DLDecoder, DLDecoderResult};
+    use std::io::Cursor;
+
+    const WIDTH: usize = 300;
+    const HEIGHT: usize = 24;
+
+    /// Flat blocks, a gradient and noise, scrolled and changed between
+    /// frames.
+    fn frames() -> Vec<Vec<u8>> {
+        let mut seed = 1u32;
+        let mut noise = move || {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (seed >> 16) as u8
+        };
+        let rows: Vec<Vec<u8>> = (0..HEIGHT + 8)
+            .map(|y| {
+                (0..WIDTH)
+                    .flat_map(|x| match (x / 50 + y) % 3 {
+                        0 => [40, 80, 120],
+                        1 => [x as u8, y as u8 * 8, 255 - x as u8],
+                        _ => [noise(), noise(), noise()],
+                    })
+                    .collect()
+            })
+            .collect();
+        let mut frames = vec![];
+        for scroll in [0, 0, 3] {
+            frames.push(rows[scroll..scroll + HEIGHT].concat());
+        }
+        // a small change on top of the previous frame
+        let mut last = frames[2].clone();
+        last[(5 * WIDTH + 7) * 3..(5 * WIDTH + 40) * 3].fill(200);
+        frames.push(last);
+        frames
+    }
+
+    fn round_trip(strategy: Strategy, memcpy: bool, depth24: bool) {
+        let frames = frames();
+        let mut encoder = DLEncoder::new(strategy, memcpy, depth24);
+        if matches!(strategy, Strategy::Decomp | Strategy::Best) {
+            let mut stats16 = DeltaStats::default();
+            let mut stats8 = DeltaStats::default();
+            for rgb in &frames {
+                let (plane16, plane8) = rgb_to_planes(rgb);
+                plane16
+                    .chunks(WIDTH)
+                    .for_each(|row| stats16.add_span16(row));
+                plane8.chunks(WIDTH).for_each(|row| stats8.add_span8(row));
+            }
+            encoder.set_table(DecompTable::build(&stats16, &stats8));
+        }
+        let mut stream = vec![];
+        for rgb in &frames {
+            encoder
+                .write_frame(&mut stream, WIDTH, HEIGHT, rgb)
+                .unwrap();
+        }
+
+        let mut reader = Cursor::new(stream);
+        let mut decoder = DLDecoder::default();
+        let mut frame = 0;
+        let mut copies = 0;
+        while let Ok(result) = decoder.parse_cmd(&mut reader) {
+            copies += matches!(result, DLDecoderResult::Memcpy(..)) as usize;
+            if !matches!(result, DLDecoderResult::Setreg(0xff, 0xff)) || decoder.get_reg(0x1f) != 0
+            {
+                continue;
+            }
+            let (plane16, plane8) = rgb_to_planes(&frames[frame]);
+            let len = WIDTH * HEIGHT;
+            let mut data16 = vec![0u8; len * 2];
+            decoder.dumpbuffer(&mut data16, decoder.get_current_address_16(), len * 2);
+            let decoded16: Vec<u16> = data16
+                .chunks(2)
+                .map(|d| d[0] as u16 | (d[1] as u16) << 8)
+                .collect();
+            assert!(decoded16 == plane16, "16-bit plane of frame {}", frame);
+            if depth24 {
+                let mut data8 = vec![0u8; len];
+                decoder.dumpbuffer(&mut data8, decoder.get_current_address_8(), len);
+                assert!(data8 == plane8, "8-bit plane of frame {}", frame);
+            }
+            frame += 1;
+        }
+        assert_eq!(frame, frames.len());
+        // the scrolled frame is copied
+        assert_eq!(memcpy, copies > 0);
+    }
+
+    #[test]
+    fn raw_round_trip() {
+        round_trip(Strategy::Raw, false, true);
+    }
+
+    #[test]
+    fn fill_round_trip() {
+        round_trip(Strategy::Fill, false, true);
+    }
+
+    #[test]
+    fn rlx_round_trip() {
+        round_trip(Strategy::Rlx, false, true);
+    }
+
+    #[test]
+    fn decomp_round_trip() {
+        round_trip(Strategy::Decomp, false, true);
+    }
+
+    #[test]
+    fn best_round_trip() {
+        round_trip(Strategy::Best, false, false);
+        round_trip(Strategy::Best, false, true);
+    }
+
+    #[test]
+    fn memcpy_round_trip() {
+        for strategy in [Strategy::Raw, Strategy::Rlx, Strategy::Best] {
+            round_trip(strategy, true, true);
+        }
+    }
+}
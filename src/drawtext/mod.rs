@@ -5,6 +5,8 @@ use sdl2::{
     surface::Surface,
 };
 
+// vendored as is
+#[allow(clippy::empty_line_after_doc_comments)]
 mod font8x8;
 use font8x8::FONT8X8_BASIC;
 
@@ -0,0 +1,141 @@
+use std::fs::File;
+use std::io::{BufReader, Cursor};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use crate::dldecoder::{DLDecoder, DLDecoderResult};
+use crate::dlencoder::{rgb_to_planes, DLEncoder, DecompTable, DeltaStats, Strategy};
+
+#[derive(clap::Args)]
+pub struct EncodeArgs {
+    /// Bulk stream to write
+    #[arg(short, long)]
+    output: PathBuf,
+
+    #[arg(short, long, value_enum, default_value_t = Strategy::Best)]
+    strategy: Strategy,
+
+    /// Never copy from already written pixels
+    #[arg(long)]
+    no_memcpy: bool,
+
+    /// Also write the 8-bit plane for 24-bit colour
+    #[arg(long)]
+    depth24: bool,
+
+    /// Decode the written stream and compare it against the input frames
+    #[arg(long)]
+    verify: bool,
+
+    #[arg(value_name = "PNG", required = true)]
+    frames: Vec<PathBuf>,
+}
+
+struct Image {
+    width: usize,
+    height: usize,
+    rgb: Vec<u8>,
+}
+
+fn load_png(path: &Path) -> Image {
+    let f = File::open(path).expect("Failed to open frame");
+    let mut decoder = png::Decoder::new(BufReader::new(f));
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().expect("Failed to read png");
+    let mut buf = vec![0u8; reader.output_buffer_size().unwrap()];
+    let info = reader.next_frame(&mut buf).expect("Failed to decode png");
+    let channels = info.color_type.samples();
+    let rgb = buf[..info.buffer_size()]
+        .chunks_exact(channels)
+        .flat_map(|p| match channels {
+            1 | 2 => [p[0], p[0], p[0]],
+            _ => [p[0], p[1], p[2]],
+        })
+        .collect();
+    Image {
+        width: info.width as usize,
+        height: info.height as usize,
+        rgb,
+    }
+}
+
+pub fn run(args: EncodeArgs) {
+    let images: Vec<Image> = args.frames.iter().map(|p| load_png(p)).collect();
+
+    let mut encoder = DLEncoder::new(args.strategy, !args.no_memcpy, args.depth24);
+    if matches!(args.strategy, Strategy::Decomp | Strategy::Best) {
+        let mut stats16 = DeltaStats::default();
+        let mut stats8 = DeltaStats::default();
+        for img in &images {
+            let (plane16, plane8) = rgb_to_planes(&img.rgb);
+            for row in plane16.chunks(img.width) {
+                stats16.add_span16(row);
+            }
+            if args.depth24 {
+                for row in plane8.chunks(img.width) {
+                    stats8.add_span8(row);
+                }
+            }
+        }
+        encoder.set_table(DecompTable::build(&stats16, &stats8));
+    }
+
+    let start = Instant::now();
+    let mut stream = vec![];
+    for img in &images {
+        encoder
+            .write_frame(&mut stream, img.width, img.height, &img.rgb)
+            .unwrap();
+    }
+    println!(
+        "encoded {} frames into {} bytes ({} bytes/frame) in {:?}",
+        images.len(),
+        stream.len(),
+        stream.len() / images.len(),
+        start.elapsed()
+    );
+    std::fs::write(&args.output, &stream).expect("Failed to write bulkstream");
+
+    if args.verify {
+        verify(&stream, &images, args.depth24);
+    }
+}
+
+fn verify(stream: &[u8], images: &[Image], depth24: bool) {
+    let start = Instant::now();
+    let mut reader = Cursor::new(stream);
+    let mut decoder_ctx = DLDecoder::default();
+    let mut framecnt = 0;
+    let mut mismatches = 0;
+    while let Ok(result) = decoder_ctx.parse_cmd(&mut reader) {
+        match result {
+            DLDecoderResult::Setreg(0xff, 0xff) if decoder_ctx.get_reg(0x1f) == 0 => {
+                let Some(img) = images.get(framecnt) else {
+                    break;
+                };
+                let (plane16, plane8) = rgb_to_planes(&img.rgb);
+                let len = img.width * img.height;
+                let mut data16 = vec![0u8; len * 2];
+                let mut data8 = vec![0u8; len];
+                decoder_ctx.dumpbuffer(&mut data16, decoder_ctx.get_current_address_16(), len * 2);
+                decoder_ctx.dumpbuffer(&mut data8, decoder_ctx.get_current_address_8(), len);
+                let ok16 = data16
+                    .chunks(2)
+                    .zip(&plane16)
+                    .all(|(d, &p)| d[0] as u16 | (d[1] as u16) << 8 == p);
+                if !ok16 || (depth24 && data8 != plane8) {
+                    println!("frame {} mismatch", framecnt);
+                    mismatches += 1;
+                }
+                framecnt += 1;
+            }
+            _ => {}
+        }
+    }
+    println!(
+        "verified {} frames, {} mismatches, decoded in {:?}",
+        framecnt,
+        mismatches,
+        start.elapsed()
+    );
+}
@@ -0,0 +1,244 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use super::video::{fit, Resize};
+use super::FrameRange;
+use crate::frame::Frame;
+use crate::timestamps::Timestamps;
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub enum AnimFormat {
+    Gif,
+    Apng,
+}
+
+#[derive(clap::Args)]
+pub struct AnimArgs {
+    /// File to write, the format follows its extension unless --format is given
+    #[arg(short, long)]
+    output: PathBuf,
+
+    #[arg(long, value_enum)]
+    format: Option<AnimFormat>,
+
+    /// Constant frame rate instead of the delays between capture timestamps
+    #[arg(long)]
+    fps: Option<f64>,
+
+    /// Tint the pixels written by each command, and those read by memcpy,
+    /// like the viewer's debug draw
+    #[arg(short, long)]
+    debugdraw: bool,
+
+    /// GIF palette quantisation speed, 1 (best) to 30 (fastest)
+    #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(i32).range(1..=30))]
+    speed: i32,
+
+    #[command(flatten)]
+    range: FrameRange,
+
+    #[arg(value_name = "FILE")]
+    path: PathBuf,
+}
+
+struct AnimFrame {
+    rgb: Vec<u8>,
+    time: Option<f64>,
+    // identical frames that followed this one
+    repeats: usize,
+}
+
+/// Part of the canvas that changed since the previous frame.
+struct Rect {
+    x: usize,
+    y: usize,
+    w: usize,
+    h: usize,
+}
+
+pub fn run_anim(args: AnimArgs) {
+    let format =
+        args.format
+            .unwrap_or_else(|| match args.output.extension().and_then(|e| e.to_str()) {
+                Some("png" | "apng") => AnimFormat::Apng,
+                _ => AnimFormat::Gif,
+            });
+    let fps = match args.fps {
+        Some(fps) if fps > 0.0 => fps,
+        Some(_) => {
+            eprintln!("--fps must be positive");
+            std::process::exit(1);
+        }
+        None => 60.0,
+    };
+    let timestamps = match args.fps {
+        Some(_) => None,
+        None => Timestamps::load(&args.path),
+    };
+    if args.fps.is_none() && timestamps.is_none() {
+        eprintln!("no capture timestamps, using 60 fps");
+    }
+
+    let mut size = None;
+    let mut frames: Vec<AnimFrame> = vec![];
+    for (_, frame) in args.range.frames(&args.path) {
+        let size = *size.get_or_insert(frame.size);
+        let mut rgb = frame.to_rgb();
+        if args.debugdraw {
+            draw_debug(&frame, &mut rgb);
+        }
+        let rgb = fit(rgb, frame.size, size, Resize::Pad);
+        if let Some(last) = frames.last_mut().filter(|last| last.rgb == rgb) {
+            last.repeats += 1;
+            continue;
+        }
+        frames.push(AnimFrame {
+            rgb,
+            time: timestamps.as_ref().and_then(|t| t.at(frame.offset)),
+            repeats: 1,
+        });
+    }
+    let Some((w, h)) = size else {
+        eprintln!("no frames to write");
+        std::process::exit(1);
+    };
+
+    // a frame lasts until the next one was captured
+    let delays: Vec<f64> = (0..frames.len())
+        .map(|i| {
+            let next = frames.get(i + 1).and_then(|f| f.time);
+            match (frames[i].time, next) {
+                (Some(time), Some(next)) if next > time => next - time,
+                _ => frames[i].repeats as f64 / fps,
+            }
+        })
+        .collect();
+
+    let size = (w as usize, h as usize);
+    match format {
+        AnimFormat::Gif => write_gif(&args.output, size, &frames, &delays, args.speed)
+            .expect("Failed to write gif"),
+        AnimFormat::Apng => {
+            write_apng(&args.output, size, &frames, &delays).expect("Failed to write apng")
+        }
+    }
+    println!(
+        "wrote {} frames ({}x{}) to {}",
+        frames.len(),
+        w,
+        h,
+        args.output.display()
+    );
+}
+
+/// Blends the overlay colour of every command into the pixels it wrote, and
+/// the source colour into those a memcpy read.
+fn draw_debug(frame: &Frame, rgb: &mut [u8]) {
+    let w = frame.size.0 as usize;
+    for span in frame.dbg_spans() {
+        let start = span.y * w + span.x;
+        for i in start..start + span.len {
+            // same 20% alpha as the viewer's overlay
+            for (c, o) in rgb[i * 3..i * 3 + 3].iter_mut().zip(span.color) {
+                *c = ((*c as u32 * 204 + o as u32 * 51) / 255) as u8;
+            }
+        }
+    }
+}
+
+fn changed_rect(prev: Option<&[u8]>, rgb: &[u8], (w, h): (usize, usize)) -> Rect {
+    let Some(prev) = prev else {
+        return Rect { x: 0, y: 0, w, h };
+    };
+    let (mut x0, mut y0, mut x1, mut y1) = (w, h, 0, 0);
+    for y in 0..h {
+        for x in 0..w {
+            let i = (y * w + x) * 3;
+            if prev[i..i + 3] != rgb[i..i + 3] {
+                x0 = x0.min(x);
+                y0 = y0.min(y);
+                x1 = x1.max(x + 1);
+                y1 = y1.max(y + 1);
+            }
+        }
+    }
+    if x0 >= x1 {
+        // nothing changed, keep a single pixel
+        return Rect {
+            x: 0,
+            y: 0,
+            w: 1,
+            h: 1,
+        };
+    }
+    Rect {
+        x: x0,
+        y: y0,
+        w: x1 - x0,
+        h: y1 - y0,
+    }
+}
+
+fn crop(rgb: &[u8], w: usize, rect: &Rect) -> Vec<u8> {
+    (rect.y..rect.y + rect.h)
+        .flat_map(|y| {
+            let i = (y * w + rect.x) * 3;
+            &rgb[i..i + rect.w * 3]
+        })
+        .copied()
+        .collect()
+}
+
+fn write_gif(
+    path: &Path,
+    (w, h): (usize, usize),
+    frames: &[AnimFrame],
+    delays: &[f64],
+    speed: i32,
+) -> Result<(), gif::EncodingError> {
+    let f = File::create(path)?;
+    let mut encoder = gif::Encoder::new(BufWriter::new(f), w as u16, h as u16, &[])?;
+    encoder.set_repeat(gif::Repeat::Infinite)?;
+    let mut prev: Option<&[u8]> = None;
+    for (frame, delay) in frames.iter().zip(delays) {
+        let rect = changed_rect(prev, &frame.rgb, (w, h));
+        let pixels = crop(&frame.rgb, w, &rect);
+        // each frame gets its own palette
+        let mut gif_frame =
+            gif::Frame::from_rgb_speed(rect.w as u16, rect.h as u16, &pixels, speed);
+        gif_frame.left = rect.x as u16;
+        gif_frame.top = rect.y as u16;
+        gif_frame.dispose = gif::DisposalMethod::Keep;
+        // centiseconds, viewers treat less than 2 as slow
+        gif_frame.delay = (delay * 100.0).round().clamp(2.0, u16::MAX as f64) as u16;
+        encoder.write_frame(&gif_frame)?;
+        prev = Some(&frame.rgb);
+    }
+    Ok(())
+}
+
+fn write_apng(
+    path: &Path,
+    (w, h): (usize, usize),
+    frames: &[AnimFrame],
+    delays: &[f64],
+) -> Result<(), png::EncodingError> {
+    let f = File::create(path)?;
+    let mut encoder = png::Encoder::new(BufWriter::new(f), w as u32, h as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_animated(frames.len() as u32, 0)?;
+    let mut writer = encoder.write_header()?;
+    let mut prev: Option<&[u8]> = None;
+    for (frame, delay) in frames.iter().zip(delays) {
+        let rect = changed_rect(prev, &frame.rgb, (w, h));
+        writer.reset_frame_position()?;
+        writer.set_frame_dimension(rect.w as u32, rect.h as u32)?;
+        writer.set_frame_position(rect.x as u32, rect.y as u32)?;
+        writer.set_frame_delay((delay * 1000.0).round().min(u16::MAX as f64) as u16, 1000)?;
+        writer.write_image_data(&crop(&frame.rgb, w, &rect))?;
+        prev = Some(&frame.rgb);
+    }
+    writer.finish()
+}
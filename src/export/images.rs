@@ -0,0 +1,37 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+
+use super::{create_dir, FrameRange};
+
+#[derive(clap::Args)]
+pub struct PngArgs {
+    /// Directory to write frame_NNNNNN.png files into
+    #[arg(short, long, default_value = ".")]
+    output: PathBuf,
+
+    #[command(flatten)]
+    range: FrameRange,
+
+    #[arg(value_name = "FILE")]
+    path: PathBuf,
+}
+
+pub fn run_png(args: PngArgs) {
+    create_dir(&args.output);
+    let mut written = 0;
+    for (i, frame) in args.range.frames(&args.path) {
+        let (w, h) = frame.size;
+        let path = args.output.join(format!("frame_{:06}.png", i));
+        let f = File::create(&path).expect("Failed to create png");
+        let mut encoder = png::Encoder::new(BufWriter::new(f), w, h);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder
+            .write_header()
+            .and_then(|mut writer| writer.write_image_data(&frame.to_rgb()))
+            .expect("Failed to write png");
+        written += 1;
+    }
+    println!("wrote {} frames to {}", written, args.output.display());
+}
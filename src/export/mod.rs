@@ -0,0 +1,58 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::num::NonZeroUsize;
+use std::path::Path;
+
+use crate::frame::{Frame, FrameDecoder};
+
+mod anim;
+pub use anim::{run_anim, AnimArgs};
+
+mod images;
+pub use images::{run_png, PngArgs};
+
+mod video;
+pub use video::{run_video, VideoArgs};
+
+/// Selection of committed frames shared by the exporters.
+#[derive(clap::Args, Clone, Copy)]
+pub struct FrameRange {
+    /// First frame to export
+    #[arg(long, default_value_t = 0)]
+    start: usize,
+
+    /// Last frame to export
+    #[arg(long)]
+    end: Option<usize>,
+
+    /// Export only every Nth frame
+    #[arg(long, default_value = "1")]
+    every: NonZeroUsize,
+}
+
+impl FrameRange {
+    pub fn contains(&self, i: usize) -> bool {
+        i >= self.start && (i - self.start).is_multiple_of(self.every.get()) && !self.is_past(i)
+    }
+
+    /// Whether frame `i` and all later ones are outside the range.
+    pub fn is_past(&self, i: usize) -> bool {
+        self.end.is_some_and(|end| i > end)
+    }
+
+    /// Decodes `path`, yielding the selected frames with their index.
+    pub fn frames(&self, path: &Path) -> impl Iterator<Item = (usize, Frame)> {
+        let bulkstream_f = File::open(path).expect("Failed to open bulkstream");
+        let range = *self;
+        FrameDecoder::new(BufReader::new(bulkstream_f))
+            .enumerate()
+            .take_while(move |&(i, _)| !range.is_past(i))
+            .filter(move |&(i, _)| range.contains(i))
+            // nothing to show before the first mode set
+            .filter(|(_, frame)| frame.size.0 > 0 && frame.size.1 > 0)
+    }
+}
+
+fn create_dir(dir: &Path) {
+    std::fs::create_dir_all(dir).expect("Failed to create output directory");
+}
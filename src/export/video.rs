@@ -0,0 +1,272 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use super::FrameRange;
+use crate::dldecoder::{DLDecoder, DLDecoderResult};
+use crate::timestamps::Timestamps;
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub enum VideoFormat {
+    /// YUV4MPEG2, 4:4:4 BT.601 limited range
+    Y4m,
+    /// Headerless rgb24 frames
+    Rgb,
+}
+
+#[derive(Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum Resize {
+    /// Stretch frames to the video size
+    Scale,
+    /// Centre frames in the video size, cropping larger ones
+    Pad,
+    /// Start a new file at every resolution change
+    Split,
+}
+
+#[derive(clap::Args)]
+pub struct VideoArgs {
+    /// File to write, `-` writes to stdout
+    #[arg(short, long, default_value = "-")]
+    output: PathBuf,
+
+    #[arg(long, value_enum, default_value_t = VideoFormat::Y4m)]
+    format: VideoFormat,
+
+    /// Frame rate, derived from the `.timestamps` file when not given
+    #[arg(long)]
+    fps: Option<f64>,
+
+    /// How frames that differ from the video size are written
+    #[arg(long, value_enum, default_value_t = Resize::Pad)]
+    resize: Resize,
+
+    /// Video size as WxH for scale and pad, defaults to the first frame
+    #[arg(long, value_parser = parse_size)]
+    size: Option<(u32, u32)>,
+
+    #[command(flatten)]
+    range: FrameRange,
+
+    #[arg(value_name = "FILE")]
+    path: PathBuf,
+}
+
+fn parse_size(s: &str) -> Result<(u32, u32), String> {
+    let (w, h) = s.split_once('x').ok_or("expected WxH")?;
+    match (w.parse(), h.parse()) {
+        (Ok(w), Ok(h)) if w > 0 && h > 0 => Ok((w, h)),
+        _ => Err(format!("bad size `{}`", s)),
+    }
+}
+
+fn is_stdout(path: &Path) -> bool {
+    path.as_os_str() == "-"
+}
+
+pub fn run_video(args: VideoArgs) {
+    if args.resize == Resize::Split && is_stdout(&args.output) {
+        eprintln!("--resize split needs an output file");
+        std::process::exit(1);
+    }
+    let fps = match args.fps {
+        Some(fps) if fps > 0.0 => fps,
+        Some(_) => {
+            eprintln!("--fps must be positive");
+            std::process::exit(1);
+        }
+        None => capture_fps(&args.range, &args.path).unwrap_or_else(|| {
+            eprintln!("no capture timestamps, using 60 fps");
+            60.0
+        }),
+    };
+
+    let mut segment: Option<Segment> = None;
+    let mut segments = 0;
+    let mut written = 0;
+    for (_, frame) in args.range.frames(&args.path) {
+        let new_segment = match &segment {
+            None => true,
+            Some(s) => args.resize == Resize::Split && s.size != frame.size,
+        };
+        if new_segment {
+            let size = match args.size {
+                Some(size) if args.resize != Resize::Split => size,
+                _ => frame.size,
+            };
+            let path = segment_path(&args.output, segments);
+            match Segment::create(&path, size, args.format, fps) {
+                Ok(s) => segment = Some(s),
+                Err(e) => {
+                    eprintln!("{}: {}", path.display(), e);
+                    std::process::exit(1);
+                }
+            }
+            segments += 1;
+        }
+        let segment = segment.as_mut().unwrap();
+        let rgb = fit(frame.to_rgb(), frame.size, segment.size, args.resize);
+        if segment.write_frame(&rgb).is_err() {
+            // reader went away
+            return;
+        }
+        written += 1;
+    }
+    if let Some(mut segment) = segment {
+        let _ = segment.writer.flush();
+    }
+    eprintln!("wrote {} frames in {} segment(s)", written, segments);
+}
+
+/// Average rate of the selected frames in the capture.
+fn capture_fps(range: &FrameRange, path: &Path) -> Option<f64> {
+    let timestamps = Timestamps::load(path)?;
+    let bulkstream_f = File::open(path).expect("Failed to open bulkstream");
+    let mut bulkstream = BufReader::new(bulkstream_f);
+    let mut decoder_ctx = DLDecoder::default();
+    let mut framecnt = 0;
+    let mut first = None;
+    let mut last = 0.0;
+    let mut cnt = 0;
+    while let Ok(result) = decoder_ctx.parse_cmd(&mut bulkstream) {
+        if let DLDecoderResult::Setreg(0xff, 0xff) = result {
+            if decoder_ctx.get_reg(0x1f) != 0 {
+                continue;
+            }
+            if range.is_past(framecnt) {
+                break;
+            }
+            let visible = decoder_ctx.get_width() > 0 && decoder_ctx.get_height() > 0;
+            if range.contains(framecnt) && visible {
+                if let Some(time) = timestamps.at(decoder_ctx.get_offset()) {
+                    first.get_or_insert(time);
+                    last = time;
+                    cnt += 1;
+                }
+            }
+            framecnt += 1;
+        }
+    }
+    let span = last - first?;
+    (cnt > 1 && span > 0.0).then(|| (cnt - 1) as f64 / span)
+}
+
+/// `output` for the first segment, `output_NNN.ext` for later ones.
+fn segment_path(output: &Path, n: usize) -> PathBuf {
+    if n == 0 {
+        return output.to_path_buf();
+    }
+    let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match output.extension() {
+        Some(ext) => format!("{}_{:03}.{}", stem, n, ext.to_string_lossy()),
+        None => format!("{}_{:03}", stem, n),
+    };
+    output.with_file_name(name)
+}
+
+/// Nearest neighbour scaling or centring of an RGB frame into `dst`.
+pub(super) fn fit(rgb: Vec<u8>, src: (u32, u32), dst: (u32, u32), resize: Resize) -> Vec<u8> {
+    if src == dst {
+        return rgb;
+    }
+    let (sw, sh) = (src.0 as usize, src.1 as usize);
+    let (dw, dh) = (dst.0 as usize, dst.1 as usize);
+    let (ox, oy) = (
+        (dw as isize - sw as isize) / 2,
+        (dh as isize - sh as isize) / 2,
+    );
+    let mut out = vec![0u8; dw * dh * 3];
+    for y in 0..dh {
+        for x in 0..dw {
+            let (sx, sy) = if resize == Resize::Scale {
+                (x * sw / dw, y * sh / dh)
+            } else {
+                let (sx, sy) = (x as isize - ox, y as isize - oy);
+                if sx < 0 || sy < 0 || sx as usize >= sw || sy as usize >= sh {
+                    continue;
+                }
+                (sx as usize, sy as usize)
+            };
+            let i = (sy * sw + sx) * 3;
+            let o = (y * dw + x) * 3;
+            out[o..o + 3].copy_from_slice(&rgb[i..i + 3]);
+        }
+    }
+    out
+}
+
+struct Segment {
+    writer: BufWriter<Box<dyn Write>>,
+    size: (u32, u32),
+    format: VideoFormat,
+}
+
+impl Segment {
+    fn create(
+        path: &Path,
+        size: (u32, u32),
+        format: VideoFormat,
+        fps: f64,
+    ) -> std::io::Result<Self> {
+        let writer: Box<dyn Write> = if is_stdout(path) {
+            Box::new(std::io::stdout().lock())
+        } else {
+            Box::new(File::create(path)?)
+        };
+        let mut writer = BufWriter::new(writer);
+        let (w, h) = size;
+        match format {
+            VideoFormat::Y4m => {
+                let (num, den) = frame_rate(fps);
+                writeln!(writer, "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C444", w, h, num, den)?;
+                eprintln!("{}: {}x{} at {} fps", path.display(), w, h, fps);
+            }
+            VideoFormat::Rgb => eprintln!(
+                "{}: {}x{} at {} fps, read with `ffmpeg -f rawvideo -pix_fmt rgb24 -s {}x{} -r {} -i ...`",
+                path.display(),
+                w,
+                h,
+                fps,
+                w,
+                h,
+                fps
+            ),
+        }
+        Ok(Self {
+            writer,
+            size,
+            format,
+        })
+    }
+
+    fn write_frame(&mut self, rgb: &[u8]) -> std::io::Result<()> {
+        match self.format {
+            VideoFormat::Rgb => self.writer.write_all(rgb),
+            VideoFormat::Y4m => {
+                let len = rgb.len() / 3;
+                let mut yuv = vec![0u8; len * 3];
+                for (i, p) in rgb.chunks_exact(3).enumerate() {
+                    let (r, g, b) = (p[0] as i32, p[1] as i32, p[2] as i32);
+                    yuv[i] = (((66 * r + 129 * g + 25 * b + 128) >> 8) + 16) as u8;
+                    yuv[len + i] = (((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128) as u8;
+                    yuv[len * 2 + i] = (((112 * r - 94 * g - 18 * b + 128) >> 8) + 128) as u8;
+                }
+                self.writer.write_all(b"FRAME\n")?;
+                self.writer.write_all(&yuv)
+            }
+        }
+    }
+}
+
+/// `fps` as the fraction written in the Y4M header.
+fn frame_rate(fps: f64) -> (u64, u64) {
+    let mut num = (fps * 1000.0).round().max(1.0) as u64;
+    let mut den = 1000;
+    let (mut a, mut b) = (num, den);
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    num /= a;
+    den /= a;
+    (num, den)
+}
@@ -0,0 +1,423 @@
+use std::io::prelude::*;
+
+use crate::dldecoder::{DLDecoder, DLDecoderResult};
+
+/// Snapshot of the visible framebuffer taken when a frame is committed.
+pub struct Frame {
+    pub size: (u32, u32),
+    pub data16: Vec<u8>,
+    pub data8: Vec<u8>,
+    pub addr16: usize,
+    pub addr8: usize,
+    pub dbg: Vec<DLDecoderResult>,
+    pub reg: [u8; 256],
+    /// Stream offset of the command that committed the frame.
+    pub offset: u64,
+    /// Frames committed when it was taken.
+    pub count: u64,
+    /// Back buffer addresses at the time, once a page flip was seen.
+    pub back: Option<(usize, usize)>,
+    /// Areas that may differ from the previous committed frame, the whole
+    /// frame when that is not known.
+    pub damage: Vec<DamageRect>,
+}
+
+/// Area of a frame in pixels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DamageRect {
+    pub x: u32,
+    pub y: u32,
+    pub w: u32,
+    pub h: u32,
+}
+
+// beyond this many rectangles the bounding box is cheaper to upload
+const MAX_DAMAGE_RECTS: usize = 32;
+
+impl Frame {
+    /// Takes the framebuffer currently set in the decoder's registers.
+    pub fn capture(decoder_ctx: &DLDecoder, dbg: Vec<DLDecoderResult>) -> Self {
+        let addr16 = decoder_ctx.get_current_address_16();
+        let addr8 = decoder_ctx.get_current_address_8();
+        let w = decoder_ctx.get_width();
+        let h = decoder_ctx.get_height();
+        let len = w * h;
+        let mut data16 = vec![0u8; len * 2];
+        let mut data8 = vec![0u8; len];
+        let mut reg = [0u8; 256];
+        decoder_ctx.dumpbuffer(&mut data16, addr16, len * 2);
+        decoder_ctx.dumpbuffer(&mut data8, addr8, len);
+        decoder_ctx.dumpreg(&mut reg);
+        let damage = vec![DamageRect {
+            x: 0,
+            y: 0,
+            w: w as u32,
+            h: h as u32,
+        }];
+        Frame {
+            size: (w as u32, h as u32),
+            data16,
+            data8,
+            addr16,
+            addr8,
+            dbg,
+            reg,
+            offset: decoder_ctx.get_offset(),
+            count: decoder_ctx.get_frame_count(),
+            back: decoder_ctx.get_back_buffer(),
+            damage,
+        }
+    }
+
+    /// Rectangles covering the pixels written by the commands in `dbg`.
+    /// Runs on a row are joined, then rows with the same runs are stacked.
+    pub fn dbg_damage(&self) -> Vec<DamageRect> {
+        let mut spans: Vec<(usize, usize, usize)> = self
+            .dbg_spans()
+            .into_iter()
+            .filter(|span| !span.source)
+            .map(|span| (span.y, span.x, span.x + span.len))
+            .collect();
+        spans.sort_unstable();
+        // disjoint runs per row
+        let mut runs: Vec<(usize, usize, usize)> = vec![];
+        for (y, start, end) in spans {
+            match runs.last_mut() {
+                Some((ry, _, rend)) if *ry == y && start <= *rend => *rend = end.max(*rend),
+                _ => runs.push((y, start, end)),
+            }
+        }
+        let mut rects: Vec<DamageRect> = vec![];
+        // rectangles reaching the previous and the current row
+        let (mut above, mut row): (Vec<usize>, Vec<usize>) = (vec![], vec![]);
+        let mut last_y = None;
+        for (y, start, end) in runs {
+            let (x, y, w) = (start as u32, y as u32, (end - start) as u32);
+            if last_y != Some(y) {
+                above = if last_y == y.checked_sub(1) {
+                    std::mem::take(&mut row)
+                } else {
+                    vec![]
+                };
+                row.clear();
+                last_y = Some(y);
+            }
+            match above.iter().find(|&&i| rects[i].x == x && rects[i].w == w) {
+                Some(&i) => {
+                    rects[i].h += 1;
+                    row.push(i);
+                }
+                None => {
+                    row.push(rects.len());
+                    rects.push(DamageRect { x, y, w, h: 1 });
+                }
+            }
+        }
+        if rects.len() > MAX_DAMAGE_RECTS {
+            let x0 = rects.iter().map(|r| r.x).min().unwrap();
+            let y0 = rects.iter().map(|r| r.y).min().unwrap();
+            let x1 = rects.iter().map(|r| r.x + r.w).max().unwrap();
+            let y1 = rects.iter().map(|r| r.y + r.h).max().unwrap();
+            rects = vec![DamageRect {
+                x: x0,
+                y: y0,
+                w: x1 - x0,
+                h: y1 - y0,
+            }];
+        }
+        rects
+    }
+
+    /// RGB565 and 8-bit plane values of pixel `i`.
+    pub fn pixel(&self, i: usize) -> (u16, u8) {
+        let h = (self.data16[i * 2 + 1] as u16) << 8 | self.data16[i * 2] as u16;
+        (h, self.data8[i])
+    }
+
+    /// RGB888 colour of pixel `i`, combining the RGB565 and 8-bit planes.
+    pub fn rgb(&self, i: usize) -> [u8; 3] {
+        let (h, b) = self.pixel(i);
+        combine(h, b)
+    }
+
+    pub fn to_rgb(&self) -> Vec<u8> {
+        (0..self.data8.len()).flat_map(|i| self.rgb(i)).collect()
+    }
+}
+
+/// RGB888 colour from an RGB565 value and the 8-bit plane byte holding the
+/// low bits.
+pub fn combine(h: u16, b: u8) -> [u8; 3] {
+    [
+        ((h & 0xf800) >> 8) as u8 | b >> 5,
+        ((h & 0x7e0) >> 3) as u8 | ((b >> 3) & 3),
+        ((h & 0x1f) << 3) as u8 | b & 7,
+    ]
+}
+
+/// Colour a command is drawn with in the debug overlay, lighter for the
+/// 8-bit plane.
+pub fn dbg_color(cmd: &DLDecoderResult) -> [u8; 3] {
+    let (color, is16) = match *cmd {
+        DLDecoderResult::Fill(_, _, is16) => ([255, 0, 0], is16),
+        DLDecoderResult::Decomp(_, _, is16) => ([0, 255, 0], is16),
+        DLDecoderResult::Memcpy(_, _, is16, _) => ([0, 0, 255], is16),
+        DLDecoderResult::Raw(_, _, is16) => ([255, 255, 0], is16),
+        DLDecoderResult::Rlx(_, _, is16) => ([255, 0, 255], is16),
+        _ => return [0, 0, 0],
+    };
+    plane_color(color, is16)
+}
+
+/// Colour of the pixels a memcpy read.
+pub fn dbg_source_color(is16: bool) -> [u8; 3] {
+    plane_color([0, 255, 255], is16)
+}
+
+fn plane_color(color: [u8; 3], is16: bool) -> [u8; 3] {
+    if is16 {
+        color
+    } else {
+        color.map(|c| c / 2 + 128)
+    }
+}
+
+/// Names and 16-bit/8-bit colours of the debug overlay.
+pub fn dbg_legend() -> Vec<(&'static str, [u8; 3], [u8; 3])> {
+    let entries = [
+        ("fill", DLDecoderResult::Fill(0, 0, true)),
+        ("decomp", DLDecoderResult::Decomp(0, 0, true)),
+        ("raw", DLDecoderResult::Raw(0, 0, true)),
+        ("rlx", DLDecoderResult::Rlx(0, 0, true)),
+        ("memcpy", DLDecoderResult::Memcpy(0, 0, true, 0)),
+    ];
+    let mut legend: Vec<_> = entries
+        .iter()
+        .map(|(name, cmd)| {
+            let color = dbg_color(cmd);
+            (*name, color, plane_color(color, false))
+        })
+        .collect();
+    legend.push((
+        "memcpy src",
+        dbg_source_color(true),
+        dbg_source_color(false),
+    ));
+    legend
+}
+
+/// A run of pixels on one row of the debug overlay.
+pub struct DbgSpan {
+    pub x: usize,
+    pub y: usize,
+    pub len: usize,
+    pub color: [u8; 3],
+    // pixels read by a memcpy
+    pub source: bool,
+}
+
+impl Frame {
+    /// Overlay spans of the commands in `dbg`, split at the end of rows.
+    /// Addresses are taken relative to the plane each command writes, and
+    /// memcpy sources get a span of their own.
+    pub fn dbg_spans(&self) -> Vec<DbgSpan> {
+        let (w, h) = (self.size.0 as usize, self.size.1 as usize);
+        let mut spans = vec![];
+        if w == 0 {
+            return spans;
+        }
+        for cmd in &self.dbg {
+            let (dst, len, is16, src) = match *cmd {
+                DLDecoderResult::Fill(addr, len, is16)
+                | DLDecoderResult::Decomp(addr, len, is16)
+                | DLDecoderResult::Raw(addr, len, is16)
+                | DLDecoderResult::Rlx(addr, len, is16) => (addr, len, is16, None),
+                DLDecoderResult::Memcpy(addr, len, is16, src) => (addr, len, is16, Some(src)),
+                _ => continue,
+            };
+            let (base, bpp) = if is16 {
+                (self.addr16, 2)
+            } else {
+                (self.addr8, 1)
+            };
+            let runs = [
+                (Some(dst), dbg_color(cmd), false),
+                (src, dbg_source_color(is16), true),
+            ];
+            for (addr, color, source) in runs {
+                let Some((start, left)) = addr.and_then(|a| dbg_clip(a, len, base, bpp)) else {
+                    continue;
+                };
+                let (mut x, mut y, mut left) = (start % w, start / w, left);
+                while left > 0 && y < h {
+                    let n = left.min(w - x);
+                    spans.push(DbgSpan {
+                        x,
+                        y,
+                        len: n,
+                        color,
+                        source,
+                    });
+                    left -= n;
+                    x = 0;
+                    y += 1;
+                }
+            }
+        }
+        spans
+    }
+}
+
+/// Pixel offset from `base` and length of a run of `len` pixels at `addr`,
+/// cut to start at `base`. Addresses wrap at the end of video RAM as the
+/// decoder's writes do.
+fn dbg_clip(addr: usize, len: usize, base: usize, bpp: usize) -> Option<(usize, usize)> {
+    const RAM: usize = 1 << 24;
+    let rel = addr.wrapping_sub(base) & (RAM - 1);
+    if rel + len * bpp <= RAM {
+        return Some((rel / bpp, len));
+    }
+    // starts below the plane and runs into it
+    let skip = (RAM - rel).div_ceil(bpp);
+    (skip < len).then(|| (0, len - skip))
+}
+
+/// Runs the decoder over a bulk stream, yielding a frame at every unlock.
+pub struct FrameDecoder<R: BufRead> {
+    reader: R,
+    decoder_ctx: DLDecoder,
+    // pixel commands since the last commit
+    dbg: Vec<DLDecoderResult>,
+    // size and addresses of the last committed frame
+    last: Option<((u32, u32), usize, usize)>,
+}
+
+impl<R: BufRead> FrameDecoder<R> {
+    pub fn new(reader: R) -> Self {
+        Self::with_decoder(reader, DLDecoder::default())
+    }
+
+    /// Continues decoding `reader` from existing decoder state.
+    pub fn with_decoder(reader: R, decoder_ctx: DLDecoder) -> Self {
+        Self {
+            reader,
+            decoder_ctx,
+            dbg: vec![],
+            last: None,
+        }
+    }
+
+    /// Decoder state after the last executed command.
+    pub fn decoder(&self) -> &DLDecoder {
+        &self.decoder_ctx
+    }
+
+    pub fn decoder_mut(&mut self) -> &mut DLDecoder {
+        &mut self.decoder_ctx
+    }
+
+    /// Executes a single command, also returning the frame if it was a
+    /// commit. `None` at the end of the stream.
+    pub fn step(&mut self) -> Option<(DLDecoderResult, Option<Frame>)> {
+        let count = self.decoder_ctx.get_frame_count();
+        let result = self.decoder_ctx.parse_cmd(&mut self.reader).ok()?;
+        if self.decoder_ctx.get_frame_count() != count {
+            // display new frame
+            let dbg = std::mem::take(&mut self.dbg);
+            let frame = self.with_damage(Frame::capture(&self.decoder_ctx, dbg));
+            self.last = Some((frame.size, frame.addr16, frame.addr8));
+            return Some((result, Some(frame)));
+        }
+        if let DLDecoderResult::Fill(..)
+        | DLDecoderResult::Memcpy(..)
+        | DLDecoderResult::Decomp(..)
+        | DLDecoderResult::Raw(..)
+        | DLDecoderResult::Rlx(..) = result
+        {
+            self.dbg.push(result);
+        }
+        Some((result, None))
+    }
+
+    /// The framebuffer as it is now, in the middle of a frame, damaged
+    /// since the last committed frame.
+    pub fn partial(&self) -> Frame {
+        self.with_damage(Frame::capture(&self.decoder_ctx, self.dbg.clone()))
+    }
+
+    // only the written areas changed if the same framebuffer is shown
+    fn with_damage(&self, mut frame: Frame) -> Frame {
+        if self.last == Some((frame.size, frame.addr16, frame.addr8)) {
+            frame.damage = frame.dbg_damage();
+        }
+        frame
+    }
+}
+
+impl<R: BufRead> Iterator for FrameDecoder<R> {
+    type Item = Frame;
+
+    fn next(&mut self) -> Option<Frame> {
+        loop {
+            if let (_, Some(frame)) = self.step()? {
+                return Some(frame);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 8x4 frame with the 16-bit plane at 0x1000 and the 8-bit one at 0
+    fn frame(dbg: Vec<DLDecoderResult>) -> Frame {
+        Frame {
+            size: (8, 4),
+            data16: vec![0; 64],
+            data8: vec![0; 32],
+            addr16: 0x1000,
+            addr8: 0,
+            dbg,
+            reg: [0; 256],
+            offset: 0,
+            count: 0,
+            back: None,
+            damage: vec![],
+        }
+    }
+
+    fn rect(x: u32, y: u32, w: u32, h: u32) -> DamageRect {
+        DamageRect { x, y, w, h }
+    }
+
+    #[test]
+    fn damage_merges_rows_and_stacks_runs() {
+        let frame = frame(vec![
+            DLDecoderResult::Fill(0x1000, 3, true),
+            DLDecoderResult::Raw(0x1000 + 2 * 2, 3, true),
+            DLDecoderResult::Fill(0x1000 + 8 * 2, 5, true),
+            DLDecoderResult::Raw(3 * 8 + 1, 2, false),
+        ]);
+        assert_eq!(frame.dbg_damage(), [rect(0, 0, 5, 2), rect(1, 3, 2, 1)]);
+    }
+
+    #[test]
+    fn spans_below_the_plane_are_clipped() {
+        let frame = frame(vec![
+            // two pixels below the 16-bit plane
+            DLDecoderResult::Fill(0x1000 - 4, 4, true),
+            // wraps at the end of video RAM into the 8-bit plane
+            DLDecoderResult::Fill(0xff_fffe, 5, false),
+            // wholly below the 16-bit plane
+            DLDecoderResult::Fill(0x800, 4, true),
+        ]);
+        let spans: Vec<_> = frame
+            .dbg_spans()
+            .iter()
+            .map(|span| (span.x, span.y, span.len))
+            .collect();
+        assert_eq!(spans, [(0, 0, 2), (0, 0, 3)]);
+        assert_eq!(frame.dbg_damage(), [rect(0, 0, 3, 1)]);
+    }
+}
@@ -0,0 +1,94 @@
+use std::collections::VecDeque;
+
+use crate::frame::{DbgSpan, Frame};
+
+/// How often each pixel was written over the last `window` frames.
+pub struct Heatmap {
+    window: usize,
+    size: (u32, u32),
+    counts: Vec<u32>,
+    // written spans of the frames counted, oldest first
+    frames: VecDeque<Vec<DbgSpan>>,
+}
+
+impl Heatmap {
+    pub fn new(window: usize) -> Self {
+        Heatmap {
+            window,
+            size: (0, 0),
+            counts: vec![],
+            frames: VecDeque::new(),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.counts.iter_mut().for_each(|c| *c = 0);
+        self.frames.clear();
+    }
+
+    /// Adds the writes of a committed frame, dropping the oldest frame once
+    /// the window is full. A resolution change starts over.
+    pub fn push(&mut self, frame: &Frame) {
+        if frame.size != self.size {
+            self.size = frame.size;
+            self.counts = vec![0; frame.size.0 as usize * frame.size.1 as usize];
+            self.frames.clear();
+        }
+        let spans: Vec<DbgSpan> = frame
+            .dbg_spans()
+            .into_iter()
+            .filter(|span| !span.source)
+            .collect();
+        self.add(&spans, 1);
+        self.frames.push_back(spans);
+        if self.frames.len() > self.window {
+            let oldest = self.frames.pop_front().unwrap();
+            self.add(&oldest, -1);
+        }
+    }
+
+    fn add(&mut self, spans: &[DbgSpan], n: i32) {
+        let w = self.size.0 as usize;
+        for span in spans {
+            let start = span.y * w + span.x;
+            for c in &mut self.counts[start..start + span.len] {
+                *c = c.wrapping_add_signed(n);
+            }
+        }
+    }
+
+    /// Number of frames counted so far, up to the window.
+    pub fn frames(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn count(&self, (x, y): (u32, u32)) -> u32 {
+        self.counts
+            .get((y * self.size.0 + x) as usize)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// RGBA overlay, transparent where nothing was written and from blue to
+    /// red up to one write per frame.
+    pub fn render(&self) -> Vec<u8> {
+        let frames = self.frames().max(1) as f32;
+        let mut rgba = Vec::with_capacity(self.counts.len() * 4);
+        for &count in &self.counts {
+            if count == 0 {
+                rgba.extend([0, 0, 0, 0]);
+            } else {
+                let [r, g, b] = color((count as f32 / frames).min(1.0));
+                rgba.extend([r, g, b, 160]);
+            }
+        }
+        rgba
+    }
+}
+
+/// Blue, cyan, green, yellow, red for `t` from 0 to 1.
+pub fn color(t: f32) -> [u8; 3] {
+    let ramp = |v: f32| (v.clamp(0.0, 1.0) * 255.0) as u8;
+    let t = t * 4.0;
+    [ramp(t - 2.0), ramp(t.min(4.0 - t)), ramp(2.0 - t)]
+}
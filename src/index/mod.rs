@@ -0,0 +1,253 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use crate::dldecoder::{DLDecoder, DLDecoderResult};
+use crate::frame::{Frame, FrameDecoder};
+
+mod sidecar;
+
+/// Most snapshots an index holds. Past that every other one is dropped and
+/// the interval doubles, so long captures seek slower instead of filling
+/// memory.
+const MAX_KEYFRAMES: usize = 32;
+
+#[derive(clap::Args)]
+pub struct IndexArgs {
+    /// Frames between the snapshots kept for seeking, doubled whenever more
+    /// than 32 would be kept
+    #[arg(long, default_value_t = 100, value_parser = clap::value_parser!(u64).range(1..))]
+    keyframes: u64,
+
+    /// Leave the snapshots out, seeking then replays from the start
+    #[arg(long)]
+    no_snapshots: bool,
+
+    #[arg(value_name = "FILE")]
+    path: PathBuf,
+}
+
+pub fn run(args: IndexArgs) {
+    let index = StreamIndex::build(&args.path, DLDecoder::default(), args.keyframes);
+    index
+        .save(&args.path, !args.no_snapshots)
+        .expect("Failed to write index");
+    let (_, frames) = index.range();
+    println!(
+        "wrote {}: {} frames, {} table loads",
+        StreamIndex::sidecar_path(&args.path).display(),
+        frames,
+        index.tables().len()
+    );
+    for &(count, w, h) in index.modes() {
+        println!("frame {}: {}x{}", count - 1, w, h);
+    }
+}
+
+/// Index of `path` from the sidecar file if it is current, otherwise built
+/// and saved for next time.
+pub fn load_or_build(path: &Path, interval: u64) -> StreamIndex {
+    match StreamIndex::load(path) {
+        Ok(index) => return index,
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            println!("rebuilding index: {}", e);
+        }
+        Err(_) => {}
+    }
+    let index = StreamIndex::build(path, DLDecoder::default(), interval);
+    if let Err(e) = index.save(path, true) {
+        println!("cannot write index: {}", e);
+    }
+    index
+}
+
+/// Frame boundaries of a bulk stream with decoder snapshots taken every
+/// `interval` frames, or a multiple of it on long streams, for jumping to
+/// any frame without replaying the whole stream.
+///
+/// Positions are frame counts: position `n` is the state right after the
+/// `n`th commit, showing frame `n - 1`.
+pub struct StreamIndex {
+    // frame count of the state decoding started from
+    base: u64,
+    // stream offset following each commit
+    offsets: Vec<u64>,
+    // (position, width, height) of the first frame of every resolution
+    modes: Vec<(u64, u16, u16)>,
+    // (position, stream offset) of every table load, position being the
+    // number of frames committed before it
+    tables: Vec<(u64, u64)>,
+    // (frame count, snapshot), the first one is the starting state
+    keyframes: Vec<(u64, Vec<u8>)>,
+}
+
+/// Opens `path` for reading from `offset`.
+pub fn open_at(path: &Path, offset: u64) -> BufReader<File> {
+    let mut bulkstream_f = File::open(path).expect("Failed to open bulkstream");
+    bulkstream_f
+        .seek(SeekFrom::Start(offset))
+        .expect("Failed to seek bulkstream");
+    BufReader::new(bulkstream_f)
+}
+
+fn snapshot(decoder_ctx: &DLDecoder) -> Vec<u8> {
+    let mut buf = vec![];
+    decoder_ctx.write_snapshot(&mut buf).unwrap();
+    buf
+}
+
+impl StreamIndex {
+    /// Decodes `path` from the state in `decoder_ctx` to the end.
+    pub fn build(path: &Path, decoder_ctx: DLDecoder, interval: u64) -> Self {
+        Self::build_capped(path, decoder_ctx, interval, MAX_KEYFRAMES)
+    }
+
+    fn build_capped(
+        path: &Path,
+        mut decoder_ctx: DLDecoder,
+        mut interval: u64,
+        max_keyframes: usize,
+    ) -> Self {
+        let base = decoder_ctx.get_frame_count();
+        let mut bulkstream = open_at(path, decoder_ctx.get_next_offset());
+        let mut index = Self {
+            base,
+            offsets: vec![],
+            modes: vec![],
+            tables: vec![],
+            keyframes: vec![(base, snapshot(&decoder_ctx))],
+        };
+        while let Ok(result) = decoder_ctx.parse_cmd(&mut bulkstream) {
+            match result {
+                DLDecoderResult::LoadTable(_) => {
+                    let count = decoder_ctx.get_frame_count();
+                    index.tables.push((count, decoder_ctx.get_offset()));
+                    continue;
+                }
+                DLDecoderResult::Setreg(0xff, 0xff) => {}
+                _ => continue,
+            }
+            let count = decoder_ctx.get_frame_count();
+            if count == base + index.offsets.len() as u64 {
+                // blanked, not a commit
+                continue;
+            }
+            index.offsets.push(decoder_ctx.get_next_offset());
+            let mode = (
+                decoder_ctx.get_width() as u16,
+                decoder_ctx.get_height() as u16,
+            );
+            if index.modes.last().is_none_or(|&(_, w, h)| (w, h) != mode) {
+                index.modes.push((count, mode.0, mode.1));
+            }
+            if (count - base).is_multiple_of(interval) {
+                index.keyframes.push((count, snapshot(&decoder_ctx)));
+                if index.keyframes.len() > max_keyframes {
+                    interval *= 2;
+                    index
+                        .keyframes
+                        .retain(|&(c, _)| (c - base).is_multiple_of(interval));
+                }
+            }
+        }
+        index
+    }
+
+    /// First and last position.
+    pub fn range(&self) -> (u64, u64) {
+        (self.base, self.base + self.offsets.len() as u64)
+    }
+
+    /// Positions where the resolution changes, with the new size.
+    pub fn modes(&self) -> &[(u64, u16, u16)] {
+        &self.modes
+    }
+
+    /// Positions and stream offsets of decompression table loads.
+    pub fn tables(&self) -> &[(u64, u64)] {
+        &self.tables
+    }
+
+    /// Restores the nearest keyframe at or before `count` and decodes
+    /// forward to it, returning the frame shown there.
+    pub fn seek(&self, path: &Path, count: u64) -> (FrameDecoder<Box<dyn BufRead + Send>>, Frame) {
+        let (first, last) = self.range();
+        let count = count.clamp(first, last);
+        let idx = self.keyframes.partition_point(|&(c, _)| c <= count) - 1;
+        let (keyframe, snapshot) = &self.keyframes[idx];
+        let decoder_ctx = DLDecoder::read_snapshot(&mut &snapshot[..]).unwrap();
+        let bulkstream: Box<dyn BufRead + Send> =
+            Box::new(open_at(path, decoder_ctx.get_next_offset()));
+        let mut frames = FrameDecoder::with_decoder(bulkstream, decoder_ctx);
+        let mut frame = None;
+        for _ in *keyframe..count {
+            frame = frames.next();
+        }
+        let frame = frame.unwrap_or_else(|| Frame::capture(frames.decoder(), vec![]));
+        (frames, frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAMES: u16 = 60;
+
+    /// A 4x1 frame filled with its own number, `FRAMES` times.
+    pub(super) fn write_stream(name: &str) -> PathBuf {
+        let mut out = vec![];
+        for (addr, val) in [(0x0f, 0), (0x10, 4), (0x17, 0), (0x18, 1)] {
+            out.extend([0xaf, 0x20, addr, val]);
+        }
+        for n in 0..FRAMES {
+            let [hi, lo] = n.to_be_bytes();
+            out.extend([0xaf, 0x69, 0, 0, 0, 4, 4, hi, lo]);
+            out.extend([0xaf, 0x20, 0xff, 0xff]);
+        }
+        let path = std::env::temp_dir().join(format!("dlemu-{}-{}.bs", name, std::process::id()));
+        std::fs::write(&path, out).unwrap();
+        path
+    }
+
+    fn shown(frame: &Frame) -> u16 {
+        frame.data16[0] as u16 | (frame.data16[1] as u16) << 8
+    }
+
+    fn check_seek(index: &StreamIndex, path: &Path, count: u64) {
+        let (frames, frame) = index.seek(path, count);
+        let expected = count.min(FRAMES as u64);
+        assert_eq!(frames.decoder().get_frame_count(), expected);
+        if expected > 0 {
+            assert_eq!(shown(&frame) as u64, expected - 1, "seek to {}", count);
+        } else {
+            // nothing set up yet
+            assert_eq!(frame.size, (0, 0));
+        }
+    }
+
+    #[test]
+    fn seek_lands_on_the_frame() {
+        let path = write_stream("seek");
+        let index = StreamIndex::build(&path, DLDecoder::default(), 20);
+        assert_eq!(index.range(), (0, FRAMES as u64));
+        // the start, on a keyframe, next to one, and past the end
+        for count in [0, 1, 20, 39, 40, 59, 60, 61, 1000] {
+            check_seek(&index, &path, count);
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn keyframes_are_capped() {
+        let path = write_stream("cap");
+        let index = StreamIndex::build_capped(&path, DLDecoder::default(), 1, 4);
+        // doubled up to 16
+        let counts: Vec<u64> = index.keyframes.iter().map(|&(c, _)| c).collect();
+        assert_eq!(counts, [0, 16, 32, 48]);
+        for count in [0, 16, 17, 47, 60] {
+            check_seek(&index, &path, count);
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,222 @@
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{BufReader, BufWriter, Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use super::{snapshot, StreamIndex};
+use crate::dldecoder::DLDecoder;
+
+const INDEX_MAGIC: &[u8; 4] = b"DLIX";
+const INDEX_VERSION: u32 = 3;
+
+/// Size and FNV-1a hash of the whole stream, tying an index to one capture.
+fn fingerprint(stream: &Path) -> std::io::Result<(u64, u64)> {
+    let mut f = File::open(stream)?;
+    let mut hash: u64 = 0xcbf29ce484222325;
+    let mut size = 0;
+    let mut buf = vec![0u8; 1 << 16];
+    loop {
+        let n = f.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        for &b in &buf[..n] {
+            hash = (hash ^ b as u64).wrapping_mul(0x100000001b3);
+        }
+        size += n as u64;
+    }
+    Ok((size, hash))
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+impl StreamIndex {
+    /// Index file kept next to `stream`.
+    pub fn sidecar_path(stream: &Path) -> PathBuf {
+        stream.with_extension("dlidx")
+    }
+
+    /// Writes the index next to `stream`:
+    ///
+    /// - magic `DLIX`, u32 version, u64 stream size, u64 stream hash
+    /// - u32 count, u64 offset following each commit
+    /// - u32 count, (u64 position, u16 width, u16 height) per resolution
+    /// - u32 count, (u64 position, u64 offset) per table load
+    /// - u32 count, (u64 position, u32 length, snapshot) per keyframe, each
+    ///   snapshot a delta against the keyframe before it
+    ///
+    /// Keyframes are left out without `snapshots`. Only indexes built from
+    /// the start of the stream can be saved.
+    pub fn save(&self, stream: &Path, snapshots: bool) -> std::io::Result<()> {
+        if self.base != 0 || self.keyframes[0].1 != snapshot(&DLDecoder::default()) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "index does not start at the beginning of the stream",
+            ));
+        }
+        let (size, hash) = fingerprint(stream)?;
+        let f = File::create(Self::sidecar_path(stream))?;
+        let mut writer = BufWriter::new(f);
+        writer.write_all(INDEX_MAGIC)?;
+        writer.write_u32::<BigEndian>(INDEX_VERSION)?;
+        writer.write_u64::<BigEndian>(size)?;
+        writer.write_u64::<BigEndian>(hash)?;
+
+        writer.write_u32::<BigEndian>(self.offsets.len() as u32)?;
+        for &offset in &self.offsets {
+            writer.write_u64::<BigEndian>(offset)?;
+        }
+        writer.write_u32::<BigEndian>(self.modes.len() as u32)?;
+        for &(count, w, h) in &self.modes {
+            writer.write_u64::<BigEndian>(count)?;
+            writer.write_u16::<BigEndian>(w)?;
+            writer.write_u16::<BigEndian>(h)?;
+        }
+        writer.write_u32::<BigEndian>(self.tables.len() as u32)?;
+        for &(count, offset) in &self.tables {
+            writer.write_u64::<BigEndian>(count)?;
+            writer.write_u64::<BigEndian>(offset)?;
+        }
+        // the starting state is the empty decoder
+        let keyframes = if snapshots { &self.keyframes[1..] } else { &[] };
+        writer.write_u32::<BigEndian>(keyframes.len() as u32)?;
+        let mut base = DLDecoder::default();
+        let mut delta = vec![];
+        for (count, snapshot) in keyframes {
+            let decoder_ctx = DLDecoder::read_snapshot(&mut &snapshot[..])?;
+            delta.clear();
+            decoder_ctx.write_delta(&base, &mut delta)?;
+            writer.write_u64::<BigEndian>(*count)?;
+            writer.write_u32::<BigEndian>(delta.len() as u32)?;
+            writer.write_all(&delta)?;
+            base = decoder_ctx;
+        }
+        writer.flush()
+    }
+
+    /// Reads the index next to `stream` if it was written for this stream.
+    pub fn load(stream: &Path) -> std::io::Result<Self> {
+        let f = File::open(Self::sidecar_path(stream))?;
+        let mut reader = BufReader::new(f);
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != INDEX_MAGIC {
+            return Err(invalid("not an index"));
+        }
+        if reader.read_u32::<BigEndian>()? != INDEX_VERSION {
+            return Err(invalid("unsupported index version"));
+        }
+        let size = reader.read_u64::<BigEndian>()?;
+        let hash = reader.read_u64::<BigEndian>()?;
+        if (size, hash) != fingerprint(stream)? {
+            return Err(invalid("index belongs to a different capture"));
+        }
+
+        let mut index = Self {
+            base: 0,
+            offsets: vec![],
+            modes: vec![],
+            tables: vec![],
+            keyframes: vec![(0, snapshot(&DLDecoder::default()))],
+        };
+        for _ in 0..reader.read_u32::<BigEndian>()? {
+            index.offsets.push(reader.read_u64::<BigEndian>()?);
+        }
+        for _ in 0..reader.read_u32::<BigEndian>()? {
+            let count = reader.read_u64::<BigEndian>()?;
+            let w = reader.read_u16::<BigEndian>()?;
+            let h = reader.read_u16::<BigEndian>()?;
+            index.modes.push((count, w, h));
+        }
+        for _ in 0..reader.read_u32::<BigEndian>()? {
+            let count = reader.read_u64::<BigEndian>()?;
+            let offset = reader.read_u64::<BigEndian>()?;
+            index.tables.push((count, offset));
+        }
+        let mut base = DLDecoder::default();
+        for _ in 0..reader.read_u32::<BigEndian>()? {
+            let count = reader.read_u64::<BigEndian>()?;
+            let mut delta = vec![0u8; reader.read_u32::<BigEndian>()? as usize];
+            reader.read_exact(&mut delta)?;
+            base = DLDecoder::read_delta(&base, &mut &delta[..])?;
+            index.keyframes.push((count, snapshot(&base)));
+        }
+        Ok(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::tests::write_stream;
+
+    fn remove(stream: &Path) {
+        std::fs::remove_file(StreamIndex::sidecar_path(stream)).unwrap();
+        std::fs::remove_file(stream).unwrap();
+    }
+
+    #[test]
+    fn round_trip() {
+        let stream = write_stream("sidecar");
+        let index = StreamIndex::build(&stream, DLDecoder::default(), 20);
+        index.save(&stream, true).unwrap();
+        let loaded = StreamIndex::load(&stream).unwrap();
+        assert_eq!(loaded.offsets, index.offsets);
+        assert_eq!(loaded.modes, index.modes);
+        assert_eq!(loaded.tables, index.tables);
+        assert!(loaded.keyframes == index.keyframes);
+
+        index.save(&stream, false).unwrap();
+        let loaded = StreamIndex::load(&stream).unwrap();
+        assert_eq!(loaded.offsets, index.offsets);
+        assert_eq!(loaded.keyframes.len(), 1);
+        remove(&stream);
+    }
+
+    #[test]
+    fn stale_index_is_rejected() {
+        let stream = write_stream("stale");
+        let index = StreamIndex::build(&stream, DLDecoder::default(), 20);
+        index.save(&stream, false).unwrap();
+        let mut data = std::fs::read(&stream).unwrap();
+
+        // same size, different bytes
+        data[5] ^= 1;
+        std::fs::write(&stream, &data).unwrap();
+        let err = StreamIndex::load(&stream).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        // same bytes, more of them
+        data[5] ^= 1;
+        data.extend([0; 4]);
+        std::fs::write(&stream, &data).unwrap();
+        let err = StreamIndex::load(&stream).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        data.truncate(data.len() - 4);
+        std::fs::write(&stream, &data).unwrap();
+        assert!(StreamIndex::load(&stream).is_ok());
+        remove(&stream);
+    }
+
+    #[test]
+    fn change_far_into_a_long_stream_is_seen() {
+        let stream = write_stream("long");
+        let mut data = std::fs::read(&stream).unwrap();
+        // padding after the frames, past any one read
+        data.resize(3 << 20, 0);
+        std::fs::write(&stream, &data).unwrap();
+        let index = StreamIndex::build(&stream, DLDecoder::default(), 20);
+        index.save(&stream, false).unwrap();
+        assert!(StreamIndex::load(&stream).is_ok());
+
+        data[(1 << 20) + 12345] ^= 1;
+        std::fs::write(&stream, &data).unwrap();
+        let err = StreamIndex::load(&stream).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        remove(&stream);
+    }
+}
@@ -0,0 +1,208 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::dldecoder::DLDecoder;
+use crate::frame::combine;
+
+const RAM_MASK: usize = 0xff_ffff;
+
+/// How bytes of `gfxram` are turned into pixels.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Format {
+    /// RGB565 words at the base address.
+    Rgb565,
+    /// 8-bit plane bytes, shown as grey.
+    Gray8,
+    /// RGB565 words at the base address with their low bits in the 8-bit
+    /// plane at `base8`, as the display combines them.
+    Rgb888,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "rgb565" | "16" => Ok(Format::Rgb565),
+            "8bit" | "8" => Ok(Format::Gray8),
+            "24bit" | "24" => Ok(Format::Rgb888),
+            _ => Err(format!(
+                "unknown format `{}`, expected rgb565, 8bit or 24bit",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Format::Rgb565 => "rgb565",
+            Format::Gray8 => "8bit",
+            Format::Rgb888 => "24bit",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A window onto `gfxram` laid out by hand rather than by the registers.
+pub struct MemView {
+    pub base: usize,
+    /// 8-bit plane address for `Format::Rgb888`.
+    pub base8: usize,
+    pub width: u32,
+    /// Pixels from one row to the next.
+    pub stride: u32,
+    pub height: u32,
+    pub format: Format,
+}
+
+impl MemView {
+    /// Starts out on the framebuffer currently set in the registers.
+    pub fn new(decoder_ctx: &DLDecoder) -> Self {
+        let width = (decoder_ctx.get_width() as u32).clamp(1, 4096);
+        let height = match decoder_ctx.get_height() as u32 {
+            0 => 512,
+            h => h.min(4096),
+        };
+        Self {
+            base: decoder_ctx.get_current_address_16(),
+            base8: decoder_ctx.get_current_address_8(),
+            width,
+            stride: width,
+            height,
+            format: Format::Rgb888,
+        }
+    }
+
+    /// A framebuffer of `size` at `addr16` with its 8-bit plane at `addr8`.
+    pub fn framebuffer(addr16: usize, addr8: usize, size: (u32, u32)) -> Self {
+        Self {
+            base: addr16,
+            base8: addr8,
+            width: size.0,
+            stride: size.0,
+            height: size.1,
+            format: Format::Rgb888,
+        }
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn bpp(&self) -> usize {
+        match self.format {
+            Format::Gray8 => 1,
+            _ => 2,
+        }
+    }
+
+    /// Addresses of the word and the 8-bit plane byte making up pixel
+    /// (`x`, `y`), whichever the format uses.
+    pub fn addresses(&self, x: u32, y: u32) -> (Option<usize>, Option<usize>) {
+        let i = y as usize * self.stride as usize + x as usize;
+        match self.format {
+            Format::Rgb565 => (Some((self.base + i * 2) & RAM_MASK), None),
+            Format::Gray8 => (None, Some((self.base + i) & RAM_MASK)),
+            Format::Rgb888 => (
+                Some((self.base + i * 2) & RAM_MASK),
+                Some((self.base8 + i) & RAM_MASK),
+            ),
+        }
+    }
+
+    fn read(decoder_ctx: &DLDecoder, addr: usize, len: usize) -> Vec<u8> {
+        let mut buf = vec![0u8; len];
+        decoder_ctx.dumpbuffer(&mut buf, addr & RAM_MASK, len);
+        buf
+    }
+
+    /// RGB888 pixels of the view, row by row.
+    pub fn render(&self, decoder_ctx: &DLDecoder) -> Vec<u8> {
+        let w = self.width as usize;
+        let mut rgb = Vec::with_capacity(w * self.height as usize * 3);
+        for y in 0..self.height {
+            let (addr16, addr8) = self.addresses(0, y);
+            let words = addr16.map(|a| Self::read(decoder_ctx, a, w * 2));
+            let bytes = addr8.map(|a| Self::read(decoder_ctx, a, w));
+            for x in 0..w {
+                let h = words
+                    .as_ref()
+                    .map_or(0, |d| (d[x * 2 + 1] as u16) << 8 | d[x * 2] as u16);
+                let b = bytes.as_ref().map_or(0, |d| d[x]);
+                rgb.extend(match self.format {
+                    Format::Gray8 => [b, b, b],
+                    _ => combine(h, b),
+                });
+            }
+        }
+        rgb
+    }
+
+    /// Lines for the pixel inspector.
+    pub fn describe(&self, decoder_ctx: &DLDecoder, (x, y): (u32, u32)) -> Vec<String> {
+        let mut lines = vec![format!("({},{})", x, y)];
+        let (addr16, addr8) = self.addresses(x, y);
+        let mut h = 0;
+        let mut b = 0;
+        if let Some(addr) = addr16 {
+            let d = Self::read(decoder_ctx, addr, 2);
+            h = (d[1] as u16) << 8 | d[0] as u16;
+            lines.push(format!("16: {:06X} = {:04X}", addr, h));
+        }
+        if let Some(addr) = addr8 {
+            b = Self::read(decoder_ctx, addr, 1)[0];
+            lines.push(format!(" 8: {:06X} = {:02X}", addr, b));
+        }
+        let [r, g, bl] = match self.format {
+            Format::Gray8 => [b, b, b],
+            _ => combine(h, b),
+        };
+        lines.push(format!("rgb: {:02X}{:02X}{:02X}", r, g, bl));
+        lines
+    }
+
+    /// Moves the view by `rows` rows, wrapping around the 16 MiB.
+    pub fn scroll(&mut self, rows: i64) {
+        let step = rows * self.stride as i64;
+        let wrap = |addr: usize, bpp: i64| {
+            (addr as i64 + step * bpp).rem_euclid(RAM_MASK as i64 + 1) as usize
+        };
+        self.base = wrap(self.base, self.bpp() as i64);
+        self.base8 = wrap(self.base8, 1);
+    }
+
+    /// Changes the size by (`dw`, `dh`) pixels, the stride following the
+    /// width when they were equal.
+    pub fn resize(&mut self, dw: i32, dh: i32) {
+        let width = (self.width as i32 + dw).clamp(1, 4096) as u32;
+        if self.stride == self.width {
+            self.stride = width;
+        }
+        self.width = width;
+        self.height = (self.height as i32 + dh).clamp(1, 4096) as u32;
+    }
+
+    pub fn cycle_format(&mut self) {
+        self.format = match self.format {
+            Format::Rgb888 => Format::Rgb565,
+            Format::Rgb565 => Format::Gray8,
+            Format::Gray8 => Format::Rgb888,
+        };
+    }
+}
+
+impl fmt::Display for MemView {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "0x{:06x} {}x{} stride {} {}",
+            self.base, self.width, self.height, self.stride, self.format
+        )?;
+        if self.format == Format::Rgb888 {
+            write!(f, " 8-bit plane 0x{:06x}", self.base8)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,154 @@
+use std::collections::VecDeque;
+use std::io::BufRead;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+
+use crate::breakpoint::{self, Breakpoint, Stop};
+use crate::dldecoder::{DLDecoder, DLDecoderResult};
+use crate::frame::{Frame, FrameDecoder};
+
+pub type Stream = Box<dyn BufRead + Send>;
+
+enum Job {
+    // decode up to the next frame or breakpoint
+    Run(u64, Vec<Breakpoint>),
+    // skip queued runs of this generation and older
+    Halt(u64),
+}
+
+pub enum Reply {
+    /// A committed frame, with a copy of the decoder state at the commit if
+    /// asked for with `copy_state`.
+    Frame(Box<Frame>, Option<Box<DLDecoder>>),
+    Break(usize, DLDecoderResult, Option<Box<Frame>>),
+    End,
+    Skipped,
+}
+
+/// Decodes on a background thread, up to `buffersize` frames ahead while
+/// playing, so a heavy frame does not hold up input and drawing.
+pub struct Player {
+    frames: Arc<Mutex<FrameDecoder<Stream>>>,
+    jobs: Sender<Job>,
+    replies: Receiver<(u64, Reply)>,
+    // replies of older generations are stale
+    generation: u64,
+    in_flight: usize,
+    ready: VecDeque<Reply>,
+    buffersize: usize,
+    copy_state: Arc<AtomicBool>,
+}
+
+impl Player {
+    pub fn new(frames: FrameDecoder<Stream>, buffersize: usize) -> Self {
+        let frames = Arc::new(Mutex::new(frames));
+        let (jobs, job_receiver) = channel();
+        let (reply_sender, replies) = channel();
+        let shared = frames.clone();
+        let copy_state = Arc::new(AtomicBool::new(false));
+        let copy = copy_state.clone();
+        thread::spawn(move || {
+            let mut halted = 0;
+            for job in job_receiver {
+                let reply = match job {
+                    Job::Halt(generation) => {
+                        halted = halted.max(generation);
+                        continue;
+                    }
+                    Job::Run(generation, _) if generation <= halted => (generation, Reply::Skipped),
+                    Job::Run(generation, breakpoints) => {
+                        let mut frames = shared.lock().unwrap();
+                        let reply = match breakpoint::run(&mut frames, &breakpoints) {
+                            Some(Stop::Frame(frame)) => {
+                                let state = copy
+                                    .load(Ordering::Relaxed)
+                                    .then(|| Box::new(frames.decoder().view_copy()));
+                                Reply::Frame(frame, state)
+                            }
+                            Some(Stop::Break(i, result, frame)) => Reply::Break(i, result, frame),
+                            None => Reply::End,
+                        };
+                        // nothing past a breakpoint or the end is decoded
+                        if !matches!(reply, Reply::Frame(..)) {
+                            halted = generation;
+                        }
+                        (generation, reply)
+                    }
+                };
+                if reply_sender.send(reply).is_err() {
+                    break;
+                }
+            }
+        });
+        Self {
+            frames,
+            jobs,
+            replies,
+            generation: 1,
+            in_flight: 0,
+            ready: VecDeque::new(),
+            buffersize: buffersize.max(1),
+            copy_state,
+        }
+    }
+
+    /// Whether frames come with a copy of the decoder state, for looking at
+    /// it without waiting for the decoder.
+    pub fn copy_state(&self, on: bool) {
+        self.copy_state.store(on, Ordering::Relaxed);
+    }
+
+    /// The decoder, waiting for the frame being decoded. Stop the player
+    /// with `interrupt` before changing its state.
+    pub fn frames(&self) -> MutexGuard<'_, FrameDecoder<Stream>> {
+        self.frames.lock().unwrap()
+    }
+
+    /// Keeps the buffer of decoded frames filled while playing.
+    pub fn play(&mut self, breakpoints: &[Breakpoint]) {
+        self.request(breakpoints, self.buffersize);
+    }
+
+    /// Asks for one more frame if none is on its way.
+    pub fn step(&mut self, breakpoints: &[Breakpoint]) {
+        self.request(breakpoints, 1);
+    }
+
+    fn request(&mut self, breakpoints: &[Breakpoint], count: usize) {
+        while self.in_flight + self.ready.len() < count {
+            let job = Job::Run(self.generation, breakpoints.to_vec());
+            if self.jobs.send(job).is_err() {
+                return;
+            }
+            self.in_flight += 1;
+        }
+    }
+
+    /// The next decoded frame, breakpoint hit or end of stream, if one is
+    /// ready.
+    pub fn next(&mut self) -> Option<Reply> {
+        for (generation, reply) in self.replies.try_iter() {
+            if generation != self.generation {
+                continue;
+            }
+            self.in_flight -= 1;
+            if !matches!(reply, Reply::Skipped) {
+                self.ready.push_back(reply);
+            }
+        }
+        self.ready.pop_front()
+    }
+
+    /// Drops the frames decoded ahead and stops decoding more. True if the
+    /// decoder may be past the last frame returned.
+    pub fn interrupt(&mut self) -> bool {
+        let ahead = self.in_flight > 0 || !self.ready.is_empty();
+        let _ = self.jobs.send(Job::Halt(self.generation));
+        self.generation += 1;
+        self.in_flight = 0;
+        self.ready.clear();
+        ahead
+    }
+}
@@ -0,0 +1,96 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::dldecoder::{DLDecoder, SNAPSHOT_MAGIC};
+use crate::frame::FrameDecoder;
+
+#[derive(clap::Args)]
+pub struct DumpArgs {
+    /// RAM dump to write
+    #[arg(short, long, default_value = "ramdump.bin")]
+    output: PathBuf,
+
+    /// Write a compact snapshot, which also records the stream offset and
+    /// frame count, instead of a raw dump
+    #[arg(short, long)]
+    snapshot: bool,
+
+    /// Dump the state after this frame is committed, defaults to the last one
+    #[arg(long)]
+    frame: Option<usize>,
+
+    #[arg(value_name = "FILE")]
+    path: PathBuf,
+}
+
+pub fn run(args: DumpArgs) {
+    let bulkstream_f = File::open(&args.path).expect("Failed to open bulkstream");
+    let mut frames = FrameDecoder::new(BufReader::new(bulkstream_f));
+    let mut framecnt = 0;
+    while args.frame.is_none_or(|frame| framecnt <= frame) && frames.next().is_some() {
+        framecnt += 1;
+    }
+    if args.frame.is_some_and(|frame| framecnt <= frame) {
+        eprintln!("stream ends after {} frames", framecnt);
+        std::process::exit(1);
+    }
+    let decoder_ctx = frames.decoder();
+    if args.snapshot {
+        write_snapshot(decoder_ctx, &args.output);
+    } else {
+        write(decoder_ctx, &args.output);
+    }
+    println!(
+        "dumped state after frame {} to {}, {}",
+        framecnt as isize - 1,
+        args.output.display(),
+        resume_hint(decoder_ctx, &args.output, args.snapshot)
+    );
+}
+
+/// How to continue the viewer from a dump of `decoder_ctx` at `path`.
+pub fn resume_hint(decoder_ctx: &DLDecoder, path: &Path, snapshot: bool) -> String {
+    if snapshot {
+        format!("resume with --load {}", path.display())
+    } else {
+        format!(
+            "resume with --load {} --offset {}",
+            path.display(),
+            decoder_ctx.get_next_offset()
+        )
+    }
+}
+
+pub fn write(decoder_ctx: &DLDecoder, path: &Path) {
+    let f = File::create(path).expect("Failed to create ramdump");
+    let mut writer = BufWriter::new(f);
+    decoder_ctx
+        .write_ramdump(&mut writer)
+        .and_then(|_| writer.flush())
+        .expect("Failed to write ramdump");
+}
+
+pub fn write_snapshot(decoder_ctx: &DLDecoder, path: &Path) {
+    let f = File::create(path).expect("Failed to create snapshot");
+    let mut writer = BufWriter::new(f);
+    decoder_ctx
+        .write_snapshot(&mut writer)
+        .and_then(|_| writer.flush())
+        .expect("Failed to write snapshot");
+}
+
+/// Loads a snapshot or a raw RAM dump, told apart by the snapshot magic.
+pub fn load(path: &Path) -> DLDecoder {
+    let f = File::open(path).expect("Failed to open ramdump");
+    let mut reader = BufReader::new(f);
+    let is_snapshot = reader
+        .fill_buf()
+        .expect("Failed to read ramdump")
+        .starts_with(SNAPSHOT_MAGIC);
+    if is_snapshot {
+        DLDecoder::read_snapshot(&mut reader).expect("Failed to read snapshot")
+    } else {
+        DLDecoder::read_ramdump(&mut reader).expect("Failed to read ramdump")
+    }
+}
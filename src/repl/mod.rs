@@ -0,0 +1,181 @@
+use std::io::BufRead;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+
+use crate::breakpoint::{register, Condition};
+use crate::disasm::{disasm, number, reg_name};
+use crate::dldecoder::DLDecoder;
+use crate::index;
+use crate::memview::Format;
+
+pub const HELP: &str = "\
+step [N]          execute N commands (default 1)
+next              decode up to the next frame
+continue          resume playback
+pause             pause playback
+frame N           seek to frame N
+reg [REG]         show one register, by name or address, or all of them
+mem ADDR [LEN]    hex dump LEN bytes of video RAM (default 64)
+disasm [N]        disassemble the next N commands (default 8)
+break [SPEC]      add a breakpoint, or list them
+toggle N          enable/disable breakpoint N
+delete N          remove breakpoint N
+dump [snapshot]   write a RAM dump or snapshot of the current state
+view ADDR [WIDTH [STRIDE [FORMAT [ADDR8]]]]
+                  browse video RAM as an image, FORMAT being rgb565, 8bit
+                  or 24bit with the 8-bit plane at ADDR8
+view off          back to the framebuffer
+quit";
+
+/// A line typed on stdin while the viewer runs.
+pub enum Command {
+    Step(usize),
+    Next,
+    Continue,
+    Pause,
+    Frame(u64),
+    Reg(Option<u8>),
+    Mem(usize, usize),
+    Disasm(usize),
+    Break(Option<Condition>),
+    Toggle(usize),
+    Delete(usize),
+    Dump(bool),
+    View {
+        base: usize,
+        width: Option<u32>,
+        stride: Option<u32>,
+        format: Option<Format>,
+        base8: Option<usize>,
+    },
+    ViewOff,
+    Help,
+    Quit,
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let (name, arg) = s.trim().split_once(' ').unwrap_or((s.trim(), ""));
+        let arg = arg.trim();
+        let args: Vec<&str> = arg.split_whitespace().collect();
+        let count = |default| args.first().map_or(Ok(default), |a| number(a));
+        let command = match name {
+            "step" | "s" => Command::Step(count(1)?),
+            "next" | "n" => Command::Next,
+            "continue" | "c" => Command::Continue,
+            "pause" => Command::Pause,
+            "frame" | "f" if !args.is_empty() => Command::Frame(number(args[0])? as u64),
+            "reg" | "r" => match args.first() {
+                Some(reg) => {
+                    let addr = register(reg)?;
+                    Command::Reg(Some(
+                        addr.try_into()
+                            .map_err(|_| format!("bad register `{}`", reg))?,
+                    ))
+                }
+                None => Command::Reg(None),
+            },
+            "mem" | "m" if !args.is_empty() => {
+                let len = args.get(1).map_or(Ok(64), |a| number(a))?;
+                Command::Mem(number(args[0])?, len)
+            }
+            "disasm" | "d" => Command::Disasm(count(8)?),
+            "break" | "b" if arg.is_empty() => Command::Break(None),
+            "break" | "b" => Command::Break(Some(arg.parse()?)),
+            "toggle" if !args.is_empty() => Command::Toggle(number(args[0])?),
+            "delete" if !args.is_empty() => Command::Delete(number(args[0])?),
+            "dump" => Command::Dump(arg == "snapshot"),
+            "view" | "v" if arg == "off" => Command::ViewOff,
+            "view" | "v" if !args.is_empty() => {
+                let size = |a: &str| number(a).map(|n| n.clamp(1, 4096) as u32);
+                Command::View {
+                    base: number(args[0])?,
+                    width: args.get(1).map(|a| size(a)).transpose()?,
+                    stride: args.get(2).map(|a| size(a)).transpose()?,
+                    format: args.get(3).map(|a| a.parse()).transpose()?,
+                    base8: args.get(4).map(|a| number(a)).transpose()?,
+                }
+            }
+            "help" | "h" | "?" => Command::Help,
+            "quit" | "q" => Command::Quit,
+            _ => return Err(format!("unknown command `{}`, try help", s.trim())),
+        };
+        Ok(command)
+    }
+}
+
+/// Reads commands from stdin on a separate thread.
+pub fn spawn() -> Receiver<Command> {
+    let (sender, receiver) = channel();
+    thread::spawn(move || {
+        for line in std::io::stdin().lock().lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            match line.parse() {
+                Ok(command) => {
+                    if sender.send(command).is_err() {
+                        break;
+                    }
+                }
+                Err(e) => println!("{}", e),
+            }
+        }
+    });
+    receiver
+}
+
+pub fn print_reg(decoder_ctx: &DLDecoder, addr: Option<u8>) {
+    match addr {
+        Some(addr) => println!(
+            "0x{:02x} {}: 0x{:02x}",
+            addr,
+            reg_name(addr).unwrap_or("?"),
+            decoder_ctx.get_reg(addr)
+        ),
+        None => {
+            for row in 0..16u8 {
+                let values: Vec<String> = (0..16u8)
+                    .map(|col| format!("{:02x}", decoder_ctx.get_reg(row * 16 + col)))
+                    .collect();
+                println!("{:02x}: {}", row * 16, values.join(" "));
+            }
+        }
+    }
+}
+
+pub fn print_mem(decoder_ctx: &DLDecoder, addr: usize, len: usize) {
+    let addr = addr & 0xff_ffff;
+    let len = len.min(0x100_0000);
+    let mut buf = vec![0u8; len];
+    decoder_ctx.dumpbuffer(&mut buf, addr, len);
+    for (i, line) in buf.chunks(16).enumerate() {
+        let bytes: Vec<String> = line.iter().map(|b| format!("{:02x}", b)).collect();
+        println!("{:06x}: {}", (addr + i * 16) & 0xff_ffff, bytes.join(" "));
+    }
+}
+
+/// Disassembles the `count` commands following the state in `decoder_ctx`
+/// without executing them there.
+pub fn print_disasm(decoder_ctx: &DLDecoder, path: &Path, count: usize) {
+    let mut snapshot = vec![];
+    decoder_ctx.write_snapshot(&mut snapshot).unwrap();
+    let mut ahead = DLDecoder::read_snapshot(&mut &snapshot[..]).unwrap();
+    let mut bulkstream = index::open_at(path, ahead.get_next_offset());
+    for _ in 0..count {
+        match ahead.parse_cmd(&mut bulkstream) {
+            Ok(result) => println!("{:08x} {}", ahead.get_offset(), disasm(&ahead, &result)),
+            Err(_) => {
+                println!("end of stream");
+                break;
+            }
+        }
+    }
+}
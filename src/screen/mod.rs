@@ -0,0 +1,124 @@
+use crate::dldecoder::DLDecoder;
+use crate::memview::MemView;
+use crate::usagemap;
+
+// black columns between the front and back buffer
+const GAP: u32 = 8;
+
+/// What the window shows.
+pub enum Screen {
+    /// The framebuffer set in the registers.
+    Frame,
+    /// Video RAM laid out by hand.
+    Memory(MemView),
+    /// Usage map, dimming blocks unused in the last frame with `activity`.
+    Usage { activity: bool },
+    /// Front and back buffer side by side.
+    Buffers,
+}
+
+/// Front buffer, or the current one before the first commit, and the back
+/// buffer once there was a page flip.
+fn buffers(decoder_ctx: &DLDecoder) -> (MemView, Option<MemView>) {
+    let size = (
+        decoder_ctx.get_width() as u32,
+        decoder_ctx.get_height() as u32,
+    );
+    let (addr16, addr8) = decoder_ctx.get_front_buffer().unwrap_or((
+        decoder_ctx.get_current_address_16(),
+        decoder_ctx.get_current_address_8(),
+    ));
+    let front = MemView::framebuffer(addr16, addr8, size);
+    let back = decoder_ctx
+        .get_back_buffer()
+        .map(|(addr16, addr8)| MemView::framebuffer(addr16, addr8, size));
+    (front, back)
+}
+
+impl Screen {
+    /// Size of what is shown, `frame` being the size of the last frame.
+    pub fn size(&self, decoder_ctx: &DLDecoder, frame: (u32, u32)) -> (u32, u32) {
+        match self {
+            Screen::Frame => frame,
+            Screen::Memory(view) => view.size(),
+            Screen::Usage { .. } => usagemap::SIZE,
+            Screen::Buffers => (
+                decoder_ctx.get_width() as u32 * 2 + GAP,
+                decoder_ctx.get_height() as u32,
+            ),
+        }
+    }
+
+    /// RGB888 pixels of anything but the frame, which has its own texture.
+    pub fn render(&self, decoder_ctx: &DLDecoder) -> Option<Vec<u8>> {
+        match self {
+            Screen::Frame => None,
+            Screen::Memory(view) => Some(view.render(decoder_ctx)),
+            Screen::Usage { activity } => decoder_ctx
+                .usage_map()
+                .map(|map| usagemap::render(&map, *activity)),
+            Screen::Buffers => {
+                let (front, back) = buffers(decoder_ctx);
+                let row = front.width as usize * 3;
+                if row == 0 || front.height == 0 {
+                    return None;
+                }
+                let front = front.render(decoder_ctx);
+                let back = back.map(|back| back.render(decoder_ctx));
+                let mut rgb = Vec::with_capacity(front.len() * 2);
+                for (y, line) in front.chunks(row).enumerate() {
+                    rgb.extend_from_slice(line);
+                    rgb.extend(std::iter::repeat_n(0, GAP as usize * 3));
+                    match &back {
+                        Some(back) => rgb.extend_from_slice(&back[y * row..][..row]),
+                        None => rgb.extend(std::iter::repeat_n(0, row)),
+                    }
+                }
+                Some(rgb)
+            }
+        }
+    }
+
+    /// Pixel inspector lines for anything but the frame.
+    pub fn describe(&self, decoder_ctx: &DLDecoder, (x, y): (u32, u32)) -> Option<Vec<String>> {
+        match self {
+            Screen::Frame => None,
+            Screen::Memory(view) => Some(view.describe(decoder_ctx, (x, y))),
+            Screen::Usage { .. } => decoder_ctx
+                .usage_map()
+                .map(|map| usagemap::describe(&map, (x, y))),
+            Screen::Buffers => {
+                let (front, back) = buffers(decoder_ctx);
+                let (name, view, x) = if x < front.width {
+                    ("front", front, x)
+                } else if x >= front.width + GAP {
+                    ("back", back?, x - front.width - GAP)
+                } else {
+                    return None;
+                };
+                let mut lines = view.describe(decoder_ctx, (x, y));
+                lines[0] = format!("{} {}", name, lines[0]);
+                Some(lines)
+            }
+        }
+    }
+
+    /// Line shown at the bottom of the window.
+    pub fn status(&self, decoder_ctx: &DLDecoder) -> Option<String> {
+        match self {
+            Screen::Memory(view) => Some(view.to_string()),
+            Screen::Buffers => {
+                let (front, back) = buffers(decoder_ctx);
+                let back = match back {
+                    Some(back) => format!("{:06X}/{:06X}", back.base, back.base8),
+                    None => "no page flip yet".to_string(),
+                };
+                Some(format!(
+                    "front {:06X}/{:06X}, back {}",
+                    front.base, front.base8, back
+                ))
+            }
+            _ => None,
+        }
+    }
+}
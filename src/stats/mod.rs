@@ -0,0 +1,410 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::PathBuf;
+
+use crate::dldecoder::{DLDecoder, DLDecoderResult};
+use crate::timestamps::Timestamps;
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub enum StatsFormat {
+    Text,
+    Csv,
+}
+
+#[derive(clap::Args)]
+pub struct StatsArgs {
+    #[arg(long, value_enum, default_value_t = StatsFormat::Text)]
+    format: StatsFormat,
+
+    /// Frame rate assumed for the bandwidth without a .timestamps file
+    #[arg(short, long, default_value_t = 60)]
+    fps: u32,
+
+    /// Only print the totals
+    #[arg(long)]
+    total: bool,
+
+    #[arg(value_name = "FILE")]
+    path: PathBuf,
+}
+
+/// USB 2.0 high speed bulk limit, 13 packets of 512 bytes per microframe.
+const USB2_BULK: f64 = 53_248_000.0;
+
+const KINDS: [&str; 8] = [
+    "fill", "copy", "decomp", "raw", "rlx", "setreg", "table", "other",
+];
+
+fn kind(result: &DLDecoderResult) -> usize {
+    match result {
+        DLDecoderResult::Fill(..) => 0,
+        DLDecoderResult::Memcpy(..) => 1,
+        DLDecoderResult::Decomp(..) => 2,
+        DLDecoderResult::Raw(..) => 3,
+        DLDecoderResult::Rlx(..) => 4,
+        DLDecoderResult::Setreg(..) => 5,
+        DLDecoderResult::LoadTable(_) => 6,
+        DLDecoderResult::Sync | DLDecoderResult::Noop => 7,
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct Counts {
+    commands: usize,
+    bytes: usize,
+    pixels: usize,
+    // pixel bytes written, what the commands would take uncompressed
+    data: usize,
+    // pixels rewritten with the value they already had
+    redundant: usize,
+}
+
+impl Counts {
+    fn add(&mut self, other: &Counts) {
+        self.commands += other.commands;
+        self.bytes += other.bytes;
+        self.pixels += other.pixels;
+        self.data += other.data;
+        self.redundant += other.redundant;
+    }
+
+    /// Pixel data per stream byte.
+    fn ratio(&self) -> Option<f64> {
+        (self.data > 0).then(|| self.data as f64 / self.bytes as f64)
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct FrameStats {
+    kinds: [Counts; KINDS.len()],
+    // time the frame took to transfer
+    seconds: Option<f64>,
+}
+
+impl FrameStats {
+    fn sum(&self) -> Counts {
+        let mut total = Counts::default();
+        self.kinds.iter().for_each(|c| total.add(c));
+        total
+    }
+
+    /// Bytes per second.
+    fn bandwidth(&self) -> Option<f64> {
+        self.seconds
+            .filter(|&s| s > 0.0)
+            .map(|s| self.sum().bytes as f64 / s)
+    }
+}
+
+/// Pixels of the `new` contents at `addr` equal to `shadow`, which is then
+/// updated.
+fn redundant(shadow: &mut [u8], addr: usize, new: &[u8], bpp: usize) -> usize {
+    let mut same = 0;
+    for (i, pixel) in new.chunks(bpp).enumerate() {
+        let mut equal = true;
+        for (j, &b) in pixel.iter().enumerate() {
+            let a = (addr + i * bpp + j) % shadow.len();
+            equal &= shadow[a] == b;
+            shadow[a] = b;
+        }
+        same += equal as usize;
+    }
+    same
+}
+
+pub fn run(args: StatsArgs) {
+    let timestamps = Timestamps::load(&args.path);
+    let bulkstream_f = File::open(&args.path).expect("Failed to open bulkstream");
+    let mut bulkstream = BufReader::new(bulkstream_f);
+    let mut decoder_ctx = DLDecoder::default();
+    // video RAM before the last command
+    let mut shadow = vec![0u8; 0x100_0000];
+    let mut frames: Vec<FrameStats> = vec![];
+    let mut frame = FrameStats::default();
+    // commands after the last commit only count in the total
+    let mut rest = FrameStats::default();
+    let mut last_time = None;
+
+    while let Ok(result) = decoder_ctx.parse_cmd(&mut bulkstream) {
+        let offset = decoder_ctx.get_offset();
+        let counts = &mut frame.kinds[kind(&result)];
+        counts.commands += 1;
+        counts.bytes += decoder_ctx.get_cmd_bytes().len();
+        if let Some((range, is16)) = result.written() {
+            let bpp = if is16 { 2 } else { 1 };
+            let addr = range.start & 0xff_ffff;
+            let len = range.len().min(shadow.len());
+            let mut new = vec![0u8; len];
+            decoder_ctx.dumpbuffer(&mut new, addr, len);
+            counts.pixels += range.len() / bpp;
+            counts.data += range.len();
+            counts.redundant += redundant(&mut shadow, addr, &new, bpp);
+        }
+        let time = timestamps.as_ref().and_then(|t| t.at(offset));
+        if last_time.is_none() {
+            last_time = time;
+        }
+
+        if matches!(result, DLDecoderResult::Setreg(0xff, 0xff)) && decoder_ctx.get_reg(0x1f) == 0 {
+            frame.seconds = match (&timestamps, time, last_time) {
+                // within one transfer the time is not known
+                (Some(_), Some(time), Some(last)) => Some(time - last).filter(|&s| s > 0.0),
+                (Some(_), _, _) => None,
+                (None, _, _) => Some(1.0 / args.fps as f64),
+            };
+            last_time = time;
+            frames.push(std::mem::take(&mut frame));
+        }
+    }
+    rest.kinds = frame.kinds;
+
+    let mut out = BufWriter::new(std::io::stdout().lock());
+    let result = match args.format {
+        StatsFormat::Text => write_text(&mut out, &frames, &rest, args.total),
+        StatsFormat::Csv => write_csv(&mut out, &frames, &rest, args.total),
+    };
+    // a closed pipe is not an error
+    let _ = result.and_then(|_| out.flush());
+}
+
+fn total(frames: &[FrameStats], rest: &FrameStats) -> FrameStats {
+    let mut total = *rest;
+    for frame in frames {
+        for (t, c) in total.kinds.iter_mut().zip(&frame.kinds) {
+            t.add(c);
+        }
+    }
+    let times: Vec<f64> = frames.iter().filter_map(|f| f.seconds).collect();
+    total.seconds = (!times.is_empty()).then(|| times.iter().sum());
+    total
+}
+
+/// Bytes per second over the frames with a known time.
+fn mean_bandwidth(frames: &[FrameStats]) -> Option<f64> {
+    let timed = frames.iter().filter(|f| f.seconds.is_some());
+    let (bytes, seconds) = timed.fold((0, 0.0), |(b, s), f| {
+        (b + f.sum().bytes, s + f.seconds.unwrap())
+    });
+    (seconds > 0.0).then(|| bytes as f64 / seconds)
+}
+
+/// Minimum, median, mean, 95th percentile and maximum.
+fn distribution(mut values: Vec<f64>) -> Option<[f64; 5]> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(f64::total_cmp);
+    let at = |p: f64| values[((values.len() - 1) as f64 * p).round() as usize];
+    let mean = values.iter().sum::<f64>() / values.len() as f64;
+    Some([at(0.0), at(0.5), mean, at(0.95), at(1.0)])
+}
+
+const DISTRIBUTION: [&str; 5] = ["min", "median", "mean", "p95", "max"];
+
+fn usb_share(bandwidth: f64) -> f64 {
+    bandwidth / USB2_BULK * 100.0
+}
+
+fn write_text(
+    out: &mut dyn Write,
+    frames: &[FrameStats],
+    rest: &FrameStats,
+    only_total: bool,
+) -> std::io::Result<()> {
+    if !only_total {
+        for (i, frame) in frames.iter().enumerate() {
+            let sum = frame.sum();
+            write!(
+                out,
+                "frame {}: {} bytes, {} commands, {} pixels ({} redundant)",
+                i, sum.bytes, sum.commands, sum.pixels, sum.redundant
+            )?;
+            if let Some(bw) = frame.bandwidth() {
+                write!(
+                    out,
+                    ", {:.2} MB/s ({:.1}% of USB 2.0)",
+                    bw / 1e6,
+                    usb_share(bw)
+                )?;
+            }
+            writeln!(out)?;
+        }
+        writeln!(out)?;
+    }
+
+    let total = total(frames, rest);
+    let sum = total.sum();
+    write!(out, "{} frames, {} bytes", frames.len(), sum.bytes)?;
+    if let Some(seconds) = total.seconds {
+        write!(out, " in {:.2} s", seconds)?;
+    }
+    writeln!(out)?;
+    writeln!(
+        out,
+        "{:<8}{:>10}{:>12}{:>12}{:>12}{:>8}",
+        "type", "commands", "bytes", "pixels", "redundant", "ratio"
+    )?;
+    let rows = KINDS.iter().zip(&total.kinds).chain([(&"total", &sum)]);
+    for (name, c) in rows {
+        let ratio = c.ratio().map_or("-".to_string(), |r| format!("{:.2}", r));
+        writeln!(
+            out,
+            "{:<8}{:>10}{:>12}{:>12}{:>12}{:>8}",
+            name, c.commands, c.bytes, c.pixels, c.redundant, ratio
+        )?;
+    }
+    let sizes = frames.iter().map(|f| f.sum().bytes as f64).collect();
+    if let Some(dist) = distribution(sizes) {
+        let text: Vec<String> = DISTRIBUTION
+            .iter()
+            .zip(dist)
+            .map(|(name, v)| format!("{} {:.0}", name, v))
+            .collect();
+        writeln!(out, "frame size: {} bytes", text.join(", "))?;
+    }
+    if let Some(bw) = mean_bandwidth(frames) {
+        write!(
+            out,
+            "bandwidth: mean {:.2} MB/s ({:.1}% of USB 2.0)",
+            bw / 1e6,
+            usb_share(bw)
+        )?;
+        let peak = frames
+            .iter()
+            .enumerate()
+            .filter_map(|(i, f)| Some((i, f.bandwidth()?)))
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+        if let Some((i, peak)) = peak {
+            write!(
+                out,
+                ", peak {:.2} MB/s ({:.1}%) in frame {}",
+                peak / 1e6,
+                usb_share(peak),
+                i
+            )?;
+        }
+        writeln!(out)?;
+    }
+    if rest.sum().commands > 0 {
+        writeln!(
+            out,
+            "{} commands after the last frame are only in the total",
+            rest.sum().commands
+        )?;
+    }
+    Ok(())
+}
+
+/// A CSV row before formatting.
+struct CsvRow {
+    seconds: Option<f64>,
+    // bytes per second
+    bandwidth: Option<f64>,
+    sum: Counts,
+    kinds: [Counts; KINDS.len()],
+}
+
+impl CsvRow {
+    fn new(frame: &FrameStats, bandwidth: Option<f64>) -> Self {
+        CsvRow {
+            seconds: frame.seconds,
+            bandwidth,
+            sum: frame.sum(),
+            kinds: frame.kinds,
+        }
+    }
+
+    fn header() -> Vec<String> {
+        let mut header: Vec<String> = [
+            "frame",
+            "seconds",
+            "bytes",
+            "commands",
+            "pixels",
+            "redundant",
+            "bandwidth",
+            "usb2_percent",
+        ]
+        .map(String::from)
+        .into();
+        for kind in KINDS {
+            for column in ["commands", "bytes", "pixels", "redundant", "ratio"] {
+                header.push(format!("{}_{}", kind, column));
+            }
+        }
+        header
+    }
+
+    /// Values in `header` order after the name, empty where they do not
+    /// apply.
+    fn values(&self) -> Vec<Option<f64>> {
+        let mut values = vec![
+            self.seconds,
+            Some(self.sum.bytes as f64),
+            Some(self.sum.commands as f64),
+            Some(self.sum.pixels as f64),
+            Some(self.sum.redundant as f64),
+            self.bandwidth,
+            self.bandwidth.map(usb_share),
+        ];
+        for c in &self.kinds {
+            values.extend([
+                Some(c.commands as f64),
+                Some(c.bytes as f64),
+                Some(c.pixels as f64),
+                Some(c.redundant as f64),
+                c.ratio(),
+            ]);
+        }
+        values
+    }
+}
+
+fn write_csv_row(out: &mut dyn Write, name: &str, values: &[Option<f64>]) -> std::io::Result<()> {
+    let cells: Vec<String> = values
+        .iter()
+        .map(|v| match v {
+            Some(v) if v.fract() == 0.0 => format!("{}", v),
+            Some(v) => format!("{:.4}", v),
+            None => String::new(),
+        })
+        .collect();
+    writeln!(out, "{},{}", name, cells.join(","))
+}
+
+/// One row per frame, the total and the distribution of every column over
+/// the frames if there are any.
+fn write_csv(
+    out: &mut dyn Write,
+    frames: &[FrameStats],
+    rest: &FrameStats,
+    only_total: bool,
+) -> std::io::Result<()> {
+    let header = CsvRow::header();
+    writeln!(out, "{}", header.join(","))?;
+
+    let rows: Vec<Vec<Option<f64>>> = frames
+        .iter()
+        .map(|frame| CsvRow::new(frame, frame.bandwidth()).values())
+        .collect();
+    if !only_total {
+        for (i, values) in rows.iter().enumerate() {
+            write_csv_row(out, &i.to_string(), values)?;
+        }
+    }
+    let total = CsvRow::new(&total(frames, rest), mean_bandwidth(frames));
+    write_csv_row(out, "total", &total.values())?;
+    if rows.is_empty() {
+        return Ok(());
+    }
+    for (d, name) in DISTRIBUTION.iter().enumerate() {
+        let values: Vec<Option<f64>> = (0..header.len() - 1)
+            .map(|col| {
+                let column = rows.iter().filter_map(|row| row[col]).collect();
+                distribution(column).map(|dist| dist[d])
+            })
+            .collect();
+        write_csv_row(out, name, &values)?;
+    }
+    Ok(())
+}
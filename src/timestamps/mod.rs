@@ -0,0 +1,82 @@
+use std::path::Path;
+
+/// Capture times of the transfers making up a bulk stream, read from the
+/// `.timestamps` file `extractbulk.py` writes next to it.
+pub struct Timestamps {
+    // (stream offset, seconds), sorted by offset
+    entries: Vec<(u64, f64)>,
+}
+
+impl Timestamps {
+    pub fn load(bulkstream: &Path) -> Option<Self> {
+        let path = bulkstream.with_extension("timestamps");
+        let text = std::fs::read_to_string(&path).ok()?;
+        let (timestamps, skipped) = Self::parse(&text);
+        if skipped > 0 {
+            eprintln!("{}: skipped {} malformed lines", path.display(), skipped);
+        }
+        Some(timestamps)
+    }
+
+    /// Reads `offset seconds` lines, also returning how many other
+    /// non-blank lines, including ones with NaN or infinite times, were
+    /// skipped.
+    fn parse(text: &str) -> (Self, usize) {
+        let mut entries = vec![];
+        let mut skipped = 0;
+        for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let entry = line
+                .split_once(char::is_whitespace)
+                .and_then(|(offset, time)| Some((offset.parse().ok()?, time.trim().parse().ok()?)))
+                .filter(|&(_, time): &(u64, f64)| time.is_finite());
+            match entry {
+                Some(entry) => entries.push(entry),
+                None => skipped += 1,
+            }
+        }
+        entries.sort_by_key(|&(offset, _)| offset);
+        (Self { entries }, skipped)
+    }
+
+    /// Time of the transfer containing `offset`.
+    pub fn at(&self, offset: u64) -> Option<f64> {
+        let idx = self.entries.partition_point(|&(o, _)| o <= offset);
+        idx.checked_sub(1).map(|i| self.entries[i].1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bad_lines_are_skipped() {
+        let text = "0 1.5\r\n\r\n4096 1.75\r\ngarbage\n 2048\t1.6 \n8192 later\n";
+        let (timestamps, skipped) = Timestamps::parse(text);
+        assert_eq!(skipped, 2);
+        assert_eq!(timestamps.at(0), Some(1.5));
+        assert_eq!(timestamps.at(2047), Some(1.5));
+        assert_eq!(timestamps.at(2048), Some(1.6));
+        assert_eq!(timestamps.at(100_000), Some(1.75));
+    }
+
+    #[test]
+    fn non_finite_times_are_skipped() {
+        let (timestamps, skipped) = Timestamps::parse("0 1.0\n10 NaN\n20 inf\n30 -infinity\n");
+        assert_eq!(skipped, 3);
+        assert_eq!(timestamps.at(25), Some(1.0));
+    }
+
+    #[test]
+    fn load_reads_the_file_next_to_the_stream() {
+        let dir = std::env::temp_dir();
+        let stream = dir.join(format!("dlemu-ts-{}.bs", std::process::id()));
+        assert!(Timestamps::load(&stream).is_none());
+        let path = stream.with_extension("timestamps");
+        std::fs::write(&path, "100 2.0\n0 1.0\n").unwrap();
+        let timestamps = Timestamps::load(&stream).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(timestamps.at(50), Some(1.0));
+        assert_eq!(timestamps.at(100), Some(2.0));
+    }
+}
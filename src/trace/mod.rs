@@ -0,0 +1,171 @@
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::PathBuf;
+
+use crate::disasm::{mnemonic, position, reg_name};
+use crate::dldecoder::{DLDecoder, DLDecoderResult};
+use crate::timestamps::Timestamps;
+
+#[derive(clap::Args)]
+pub struct TraceArgs {
+    /// Also write a summary record after every frame
+    #[arg(short, long)]
+    summary: bool,
+
+    #[arg(value_name = "FILE")]
+    path: PathBuf,
+}
+
+/// One JSON object, built field by field.
+struct Record(String);
+
+impl Record {
+    fn new(kind: &str) -> Self {
+        Self(format!("{{\"type\":\"{}\"", kind))
+    }
+
+    fn field(&mut self, key: &str, value: impl Display) {
+        self.0 += &format!(",\"{}\":{}", key, value);
+    }
+
+    fn str_field(&mut self, key: &str, value: &str) {
+        self.0 += &format!(",\"{}\":\"{}\"", key, value);
+    }
+
+    fn position(&mut self, prefix: &str, pos: Option<(usize, usize)>) {
+        if let Some((x, y)) = pos {
+            self.field(&format!("{}x", prefix), x);
+            self.field(&format!("{}y", prefix), y);
+        }
+    }
+
+    fn finish(mut self, out: &mut dyn Write) -> std::io::Result<()> {
+        self.0.push('}');
+        writeln!(out, "{}", self.0)
+    }
+}
+
+#[derive(Default)]
+struct FrameSummary {
+    commands: usize,
+    bytes: usize,
+    pixels: usize,
+}
+
+pub fn run(args: TraceArgs) {
+    let timestamps = Timestamps::load(&args.path);
+    let bulkstream_f = File::open(&args.path).expect("Failed to open bulkstream");
+    let mut bulkstream = BufReader::new(bulkstream_f);
+    let mut out = BufWriter::new(std::io::stdout().lock());
+    let mut decoder_ctx = DLDecoder::default();
+    let mut reg = [0u8; 256];
+    let mut framecnt = 0;
+    // back buffer at the previous frame, a change means a page flip
+    let mut last_back = None;
+    let mut summary = FrameSummary::default();
+    // padding bytes are folded into one record
+    let mut noop: Option<(u64, usize)> = None;
+
+    while let Ok(result) = decoder_ctx.parse_cmd(&mut bulkstream) {
+        let offset = decoder_ctx.get_offset();
+        let bytes = decoder_ctx.get_cmd_bytes();
+        summary.bytes += bytes.len();
+        if let DLDecoderResult::Noop = result {
+            noop.get_or_insert((offset, 0)).1 += 1;
+            continue;
+        }
+        if let Some((offset, cnt)) = noop.take() {
+            if write_noop(&mut out, offset, framecnt, cnt).is_err() {
+                return;
+            }
+        }
+        summary.commands += 1;
+
+        let mut record = Record::new("cmd");
+        record.field("offset", offset);
+        record.field("frame", framecnt);
+        record.str_field("op", &mnemonic(&result));
+        record.field("opcode", bytes[1]);
+        record.field("len", bytes.len());
+        match result {
+            DLDecoderResult::Fill(addr, len, is16)
+            | DLDecoderResult::Decomp(addr, len, is16)
+            | DLDecoderResult::Raw(addr, len, is16)
+            | DLDecoderResult::Rlx(addr, len, is16) => {
+                record.field("dst", addr);
+                record.position("", position(&decoder_ctx, addr, is16));
+                record.field("n", len);
+                summary.pixels += len;
+            }
+            DLDecoderResult::Memcpy(addr, len, is16, src) => {
+                record.field("dst", addr);
+                record.position("", position(&decoder_ctx, addr, is16));
+                record.field("n", len);
+                record.field("src", src);
+                record.position("src_", position(&decoder_ctx, src, is16));
+                summary.pixels += len;
+            }
+            DLDecoderResult::Setreg(addr, val) => {
+                record.field("reg", addr);
+                if let Some(name) = reg_name(addr) {
+                    record.str_field("name", name);
+                }
+                record.field("old", reg[addr as usize]);
+                record.field("val", val);
+                reg[addr as usize] = val;
+            }
+            DLDecoderResult::LoadTable(cnt) => record.field("n", cnt),
+            DLDecoderResult::Sync | DLDecoderResult::Noop => {}
+        }
+        let time = timestamps.as_ref().and_then(|t| t.at(offset));
+        if let Some(time) = time {
+            record.field("time", time);
+        }
+        if record.finish(&mut out).is_err() {
+            return;
+        }
+
+        if matches!(result, DLDecoderResult::Setreg(0xff, 0xff)) && decoder_ctx.get_reg(0x1f) == 0 {
+            if args.summary {
+                let mut record = Record::new("frame");
+                record.field("frame", framecnt);
+                record.field("offset", offset);
+                record.field("commands", summary.commands);
+                record.field("bytes", summary.bytes);
+                record.field("pixels", summary.pixels);
+                record.field("width", decoder_ctx.get_width());
+                record.field("height", decoder_ctx.get_height());
+                record.field("addr16", decoder_ctx.get_current_address_16());
+                record.field("addr8", decoder_ctx.get_current_address_8());
+                let back = decoder_ctx.get_back_buffer();
+                if let Some((back16, back8)) = back {
+                    record.field("back16", back16);
+                    record.field("back8", back8);
+                }
+                record.field("flip", back != last_back);
+                last_back = back;
+                if let Some(time) = time {
+                    record.field("time", time);
+                }
+                if record.finish(&mut out).is_err() {
+                    return;
+                }
+            }
+            summary = FrameSummary::default();
+            framecnt += 1;
+        }
+    }
+    if let Some((offset, cnt)) = noop {
+        let _ = write_noop(&mut out, offset, framecnt, cnt);
+    }
+}
+
+fn write_noop(out: &mut dyn Write, offset: u64, frame: usize, cnt: usize) -> std::io::Result<()> {
+    let mut record = Record::new("cmd");
+    record.field("offset", offset);
+    record.field("frame", frame);
+    record.str_field("op", "NOP");
+    record.field("len", cnt);
+    record.finish(out)
+}
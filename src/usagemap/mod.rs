@@ -0,0 +1,65 @@
+use crate::dldecoder::{Region, BLOCK_SIZE};
+
+/// The map shows one block per pixel, 64 KiB per row.
+pub const SIZE: (u32, u32) = (256, 256);
+
+pub const LEGEND: [Region; 6] = [
+    Region::Framebuffer16,
+    Region::Plane8,
+    Region::OldFramebuffer,
+    Region::CopySource,
+    Region::Written,
+    Region::Unused,
+];
+
+pub fn name(region: Region) -> &'static str {
+    match region {
+        Region::Framebuffer16 => "16-bit framebuffer",
+        Region::Plane8 => "8-bit plane",
+        Region::OldFramebuffer => "old framebuffer",
+        Region::CopySource => "memcpy source",
+        Region::Written => "written",
+        Region::Unused => "never written",
+    }
+}
+
+pub fn color(region: Region) -> [u8; 3] {
+    match region {
+        Region::Framebuffer16 => [255, 64, 64],
+        Region::Plane8 => [255, 160, 0],
+        Region::OldFramebuffer => [160, 64, 255],
+        Region::CopySource => [0, 200, 255],
+        Region::Written => [96, 160, 96],
+        Region::Unused => [24, 24, 24],
+    }
+}
+
+/// RGB888 pixels of the map. With `activity`, blocks not touched in the
+/// last frame are dimmed.
+pub fn render(map: &[(Region, bool)], activity: bool) -> Vec<u8> {
+    map.iter()
+        .flat_map(|&(region, touched)| {
+            let rgb = color(region);
+            if activity && !touched {
+                rgb.map(|c| c / 5)
+            } else {
+                rgb
+            }
+        })
+        .collect()
+}
+
+/// Lines for the pixel inspector.
+pub fn describe(map: &[(Region, bool)], (x, y): (u32, u32)) -> Vec<String> {
+    let block = (y * SIZE.0 + x) as usize;
+    let (region, touched) = map[block];
+    let addr = block * BLOCK_SIZE;
+    let mut lines = vec![
+        format!("{:06X}..{:06X}", addr, addr + BLOCK_SIZE),
+        name(region).to_string(),
+    ];
+    if touched {
+        lines.push("used in last frame".to_string());
+    }
+    lines
+}
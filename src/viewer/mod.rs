@@ -0,0 +1,1156 @@
+use sdl2::{
+    event::Event, event::WindowEvent, keyboard::Keycode, keyboard::Mod, mouse::MouseButton,
+    pixels::Color, pixels::PixelFormatEnum, rect::Rect, render::Texture, video::FullscreenType,
+};
+use std::path::PathBuf;
+use std::sync::mpsc::channel;
+use std::thread;
+use std::thread::sleep;
+use std::time::Duration;
+
+use crate::breakpoint::{Breakpoint, Condition};
+use crate::dldecoder::{self, DLDecoder, DLDecoderResult};
+use crate::drawtext::{draw_text, generate_font_texture};
+use crate::frame::{dbg_legend, DamageRect, Frame, FrameDecoder};
+use crate::heatmap::{self, Heatmap};
+use crate::index::{self, StreamIndex};
+use crate::memview::MemView;
+use crate::player::{self, Player, Reply};
+use crate::screen::Screen;
+use crate::viewport::Viewport;
+use crate::{disasm, ramdump, repl, usagemap};
+
+#[derive(clap::Args)]
+pub struct ViewArgs {
+    #[arg(short, long)]
+    debugdraw: bool,
+
+    #[arg(short, long)]
+    info: bool,
+
+    #[arg(short, long)]
+    pause: bool,
+
+    #[arg(short, long, default_value_t = 60)]
+    fps: u32,
+
+    /// Frames decoded ahead while playing
+    #[arg(long, default_value_t = 10)]
+    buffersize: usize,
+
+    /// Start from a RAM dump instead of empty video RAM
+    #[arg(long, value_name = "DUMP")]
+    load: Option<PathBuf>,
+
+    /// Start reading the stream at this byte offset, defaults to the
+    /// offset recorded in a snapshot
+    #[arg(long)]
+    offset: Option<u64>,
+
+    /// Frames between the snapshots kept for seeking, doubled whenever more
+    /// than 32 would be kept
+    #[arg(long, default_value_t = 100, value_parser = clap::value_parser!(u64).range(1..))]
+    keyframes: u64,
+
+    /// Commands executed by Shift+C
+    #[arg(long, default_value_t = 100)]
+    command_step: usize,
+
+    /// Pause when a command meets this condition, may be repeated
+    #[arg(long = "break", value_name = "SPEC")]
+    breakpoints: Vec<Condition>,
+
+    /// Record which command last wrote each byte of video RAM, clicking a
+    /// pixel then shows it. Takes 64 MiB plus the writes still visible
+    #[arg(long)]
+    provenance: bool,
+
+    /// Frames the heatmap (H) counts writes over
+    #[arg(long, default_value_t = 60, value_parser = clap::value_parser!(u64).range(1..))]
+    heatmap: u64,
+
+    #[arg(value_name = "FILE", required_unless_present = "load")]
+    path: Option<PathBuf>,
+}
+pub fn run(args: ViewArgs) {
+    let frame_duration = Duration::new(0, 1_000_000_000u32 / args.fps);
+    let mut decoder_ctx = match &args.load {
+        Some(dump) => ramdump::load(dump),
+        None => DLDecoder::default(),
+    };
+    if let Some(offset) = args.offset {
+        decoder_ctx.set_next_offset(offset);
+    }
+    if args.provenance {
+        decoder_ctx.track_writes();
+    }
+    decoder_ctx.track_usage();
+    // a loaded dump is shown before anything is decoded
+    let mut pending = args
+        .load
+        .as_ref()
+        .map(|_| Frame::capture(&decoder_ctx, vec![]))
+        .filter(|frame| frame.size.0 > 0 && frame.size.1 > 0);
+    let bulkstream: player::Stream = match &args.path {
+        Some(path) => Box::new(index::open_at(path, decoder_ctx.get_next_offset())),
+        None => Box::new(std::io::empty()),
+    };
+    // seeking is possible once a first pass has indexed the stream
+    let (index_sender, index_receiver) = channel();
+    if let Some(path) = args.path.clone() {
+        let interval = args.keyframes;
+        // the index file describes the stream from its start
+        let fresh = args.load.is_none() && args.offset.unwrap_or(0) == 0;
+        let mut start = vec![];
+        decoder_ctx.write_snapshot(&mut start).unwrap();
+        thread::spawn(move || {
+            let index = if fresh {
+                index::load_or_build(&path, interval)
+            } else {
+                let decoder_ctx = DLDecoder::read_snapshot(&mut &start[..]).unwrap();
+                StreamIndex::build(&path, decoder_ctx, interval)
+            };
+            let _ = index_sender.send(index);
+        });
+    }
+    let mut index: Option<StreamIndex> = None;
+    let mut player = Player::new(
+        FrameDecoder::with_decoder(bulkstream, decoder_ctx),
+        args.buffersize,
+    );
+
+    let sdl_context = sdl2::init().unwrap();
+    // keep pixel boundaries crisp when zoomed
+    sdl2::hint::set("SDL_RENDER_SCALE_QUALITY", "nearest");
+    let video_subsystem = sdl_context.video().unwrap();
+
+    let window = video_subsystem
+        .window("dlemu-rs", 1280, 1024)
+        .position_centered()
+        .resizable()
+        .build()
+        .unwrap();
+
+    let mut canvas = window.into_canvas().build().unwrap();
+    let texture_creator = canvas.texture_creator();
+    let mut rendertex: Option<Texture> = None;
+    let mut debugtex: Option<Texture> = None;
+    let mut heattex: Option<Texture> = None;
+    // the frame texture is not just one frame's damage behind
+    let mut full_upload = true;
+    let font = {
+        let mut t = generate_font_texture(&texture_creator, Color::BLACK, Color::WHITE);
+        t.set_blend_mode(sdl2::render::BlendMode::Blend);
+        t.set_alpha_mod(180);
+        t
+    };
+    let mut event_pump = sdl_context.event_pump().unwrap();
+    // only wanted while typing a breakpoint
+    video_subsystem.text_input().stop();
+    let mut cur_size = (0, 0);
+    let mut framecnt = player.frames().decoder().get_frame_count();
+    let mut reg_localcopy = [0u8; 256];
+    let mut addr = (0, 0);
+    let mut playing = !args.pause;
+    let mut was_playing = playing;
+    let mut stepping = false;
+    let mut draw_debug = args.debugdraw;
+    let mut show_info = args.info;
+    let mut show_pixel = false;
+    let mut heatmap = Heatmap::new(args.heatmap as usize);
+    let mut show_heatmap = false;
+    // the heatmap changed since heattex was last rendered
+    let mut heat_stale = false;
+    // last frame drawn and the mouse position over it
+    let mut shown: Option<Frame> = None;
+    // decoder state copied when a frame was decoded ahead
+    let mut shown_state: Option<Box<DLDecoder>> = None;
+    let mut mouse = None;
+    let mut viewport = Viewport::default();
+    let mut panning = false;
+    let mut screen = Screen::Frame;
+    // texture for anything but the frame
+    let mut imagetex: Option<Texture> = None;
+    // back buffer at the last frame shown, to report page flips
+    let mut back_buffer = None;
+    let mut show_timeline = true;
+    let mut scrubbing = false;
+    let mut cmd_steps = 0;
+    // disassembly and highlight of the command last stepped over
+    let mut executed: Option<(String, Vec<(Rect, Color)>)> = None;
+    let mut breakpoints: Vec<Breakpoint> = args
+        .breakpoints
+        .iter()
+        .map(|condition| Breakpoint {
+            condition: condition.clone(),
+            enabled: true,
+        })
+        .collect();
+    let mut show_breakpoints = false;
+    // breakpoint being typed after Shift+B
+    let mut new_breakpoint: Option<String> = None;
+    let commands = repl::spawn();
+    'mainloop: loop {
+        if index.is_none() {
+            if let Ok(built) = index_receiver.try_recv() {
+                let (first, last) = built.range();
+                println!("indexed frames {}..{}", first, last);
+                index = Some(built);
+            }
+        }
+        let mut seek = None;
+        let mut picked = None;
+        player.copy_state(!matches!(screen, Screen::Frame));
+        let view_size = screen_size(
+            &screen,
+            &player,
+            state_on_screen(&shown, &shown_state),
+            cur_size,
+        );
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. } => break 'mainloop,
+                Event::MouseButtonDown {
+                    mouse_btn: MouseButton::Left,
+                    x,
+                    y,
+                    ..
+                } => {
+                    let (w, h) = canvas.output_size().unwrap();
+                    if show_timeline && index.is_some() && y >= h as i32 - TIMELINE_HEIGHT {
+                        scrubbing = true;
+                        seek = timeline_position(index.as_ref(), x, w);
+                    } else if args.provenance {
+                        // resolved against the state on screen
+                        settle(&mut player, &mut pending, &mut full_upload);
+                        picked = Some((x, y));
+                    }
+                }
+                Event::MouseButtonDown {
+                    mouse_btn: MouseButton::Right,
+                    ..
+                } => panning = true,
+                Event::MouseButtonUp {
+                    mouse_btn: MouseButton::Right,
+                    ..
+                } => panning = false,
+                Event::MouseWheel { y, .. } if view_size.0 > 0 => {
+                    let window = canvas.output_size().unwrap();
+                    let center = (window.0 as i32 / 2, window.1 as i32 / 2);
+                    viewport.zoom_at(view_size, window, mouse.unwrap_or(center), y);
+                }
+                Event::MouseMotion {
+                    mousestate,
+                    x,
+                    y,
+                    xrel,
+                    yrel,
+                    ..
+                } => {
+                    mouse = Some((x, y));
+                    if panning && mousestate.right() {
+                        viewport.drag(xrel, yrel);
+                    }
+                    if scrubbing && mousestate.left() {
+                        let (w, _) = canvas.output_size().unwrap();
+                        seek = timeline_position(index.as_ref(), x, w);
+                    }
+                }
+                Event::Window {
+                    win_event: WindowEvent::Leave,
+                    ..
+                } => mouse = None,
+                Event::MouseButtonUp {
+                    mouse_btn: MouseButton::Left,
+                    ..
+                } => scrubbing = false,
+                Event::TextInput { text, .. } => {
+                    if let Some(entry) = &mut new_breakpoint {
+                        entry.push_str(&text);
+                    }
+                }
+                Event::KeyDown { keycode, .. } if new_breakpoint.is_some() => match keycode {
+                    Some(Keycode::Return) => {
+                        video_subsystem.text_input().stop();
+                        let entry = new_breakpoint.take().unwrap();
+                        match entry.parse::<Condition>() {
+                            Ok(condition) => {
+                                println!("breakpoint {}: {}", breakpoints.len() + 1, condition);
+                                breakpoints.push(Breakpoint {
+                                    condition,
+                                    enabled: true,
+                                });
+                            }
+                            Err(e) => println!("{}", e),
+                        }
+                    }
+                    Some(Keycode::Escape) => {
+                        video_subsystem.text_input().stop();
+                        new_breakpoint = None;
+                    }
+                    Some(Keycode::Backspace) => {
+                        new_breakpoint.as_mut().unwrap().pop();
+                    }
+                    _ => {}
+                },
+                Event::KeyDown {
+                    keycode,
+                    keymod,
+                    repeat: false,
+                    ..
+                } => match keycode {
+                    Some(Keycode::Space) => playing = !playing,
+                    Some(Keycode::D) => draw_debug = !draw_debug,
+                    Some(Keycode::I) => show_info = !show_info,
+                    Some(Keycode::P) => show_pixel = !show_pixel,
+                    Some(Keycode::H) => show_heatmap = !show_heatmap,
+                    Some(Keycode::Z) => viewport.cycle(),
+                    Some(Keycode::U) => {
+                        screen = match screen {
+                            Screen::Usage { activity: false } => Screen::Usage { activity: true },
+                            Screen::Usage { activity: true } => Screen::Frame,
+                            _ => Screen::Usage { activity: false },
+                        };
+                    }
+                    Some(Keycode::V) if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) => {
+                        screen = match screen {
+                            Screen::Buffers => Screen::Frame,
+                            _ => Screen::Buffers,
+                        };
+                    }
+                    Some(Keycode::V) => {
+                        screen = match screen {
+                            Screen::Memory(_) => Screen::Frame,
+                            _ => Screen::Memory(MemView::new(player.frames().decoder())),
+                        };
+                        if let Screen::Memory(view) = &screen {
+                            println!("memory view {}", view);
+                        }
+                    }
+                    Some(
+                        k @ (Keycode::Up
+                        | Keycode::Down
+                        | Keycode::PageUp
+                        | Keycode::PageDown
+                        | Keycode::LeftBracket
+                        | Keycode::RightBracket
+                        | Keycode::Tab),
+                    ) => {
+                        let Screen::Memory(view) = &mut screen else {
+                            continue;
+                        };
+                        let shift = keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD);
+                        let rows = if shift { 16 } else { 1 };
+                        match k {
+                            Keycode::Up => view.scroll(-rows),
+                            Keycode::Down => view.scroll(rows),
+                            Keycode::PageUp => view.scroll(-(view.height as i64)),
+                            Keycode::PageDown => view.scroll(view.height as i64),
+                            Keycode::LeftBracket if shift => view.resize(0, -16),
+                            Keycode::RightBracket if shift => view.resize(0, 16),
+                            Keycode::LeftBracket => view.resize(-1, 0),
+                            Keycode::RightBracket => view.resize(1, 0),
+                            _ => view.cycle_format(),
+                        }
+                        println!("memory view {}", view);
+                    }
+                    Some(Keycode::F) => {
+                        let window = canvas.window_mut();
+                        let fullscreen = match window.fullscreen_state() {
+                            FullscreenType::Off => FullscreenType::Desktop,
+                            _ => FullscreenType::Off,
+                        };
+                        window.set_fullscreen(fullscreen).unwrap();
+                    }
+                    Some(Keycode::Q) => break 'mainloop,
+                    Some(Keycode::Period) => {
+                        playing = false;
+                        stepping = true;
+                    }
+                    Some(Keycode::Comma) => {
+                        playing = false;
+                        seek = Some(framecnt.saturating_sub(1));
+                    }
+                    Some(Keycode::Left) => seek = Some(framecnt.saturating_sub(args.fps as u64)),
+                    Some(Keycode::Right) => seek = Some(framecnt + args.fps as u64),
+                    Some(Keycode::Home) => seek = Some(0),
+                    Some(Keycode::End) => seek = Some(u64::MAX),
+                    Some(Keycode::T) => show_timeline = !show_timeline,
+                    Some(Keycode::C) => {
+                        playing = false;
+                        cmd_steps = if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                            args.command_step
+                        } else {
+                            1
+                        };
+                    }
+                    Some(Keycode::M) => {
+                        settle(&mut player, &mut pending, &mut full_upload);
+                        write_dump(player.frames().decoder(), false);
+                    }
+                    Some(Keycode::S) => {
+                        settle(&mut player, &mut pending, &mut full_upload);
+                        write_dump(player.frames().decoder(), true);
+                    }
+                    Some(Keycode::B) => {
+                        if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                            video_subsystem.text_input().start();
+                            new_breakpoint = Some(String::new());
+                            show_breakpoints = true;
+                        } else {
+                            show_breakpoints = !show_breakpoints;
+                        }
+                    }
+                    Some(
+                        k @ (Keycode::Num1
+                        | Keycode::Num2
+                        | Keycode::Num3
+                        | Keycode::Num4
+                        | Keycode::Num5
+                        | Keycode::Num6
+                        | Keycode::Num7
+                        | Keycode::Num8
+                        | Keycode::Num9),
+                    ) if show_breakpoints => {
+                        let i = (k as i32 - Keycode::Num1 as i32) as usize;
+                        if let Some(bp) = breakpoints.get_mut(i) {
+                            bp.enabled = !bp.enabled;
+                        }
+                    }
+                    _ => {}
+                },
+                _ => {}
+            }
+        }
+        for command in commands.try_iter() {
+            if let repl::Command::Reg(_)
+            | repl::Command::Mem(..)
+            | repl::Command::Disasm(_)
+            | repl::Command::Dump(_) = command
+            {
+                // what is printed matches the screen
+                settle(&mut player, &mut pending, &mut full_upload);
+            }
+            match command {
+                repl::Command::Step(n) => {
+                    playing = false;
+                    cmd_steps = n;
+                }
+                repl::Command::Next => {
+                    playing = false;
+                    stepping = true;
+                }
+                repl::Command::Continue => playing = true,
+                repl::Command::Pause => playing = false,
+                repl::Command::Frame(count) if index.is_some() => {
+                    playing = false;
+                    seek = Some(count);
+                }
+                repl::Command::Frame(_) => println!("not indexed yet"),
+                repl::Command::Reg(addr) => repl::print_reg(player.frames().decoder(), addr),
+                repl::Command::Mem(addr, len) => {
+                    repl::print_mem(player.frames().decoder(), addr, len)
+                }
+                repl::Command::Disasm(n) => match &args.path {
+                    Some(path) => repl::print_disasm(player.frames().decoder(), path, n),
+                    None => println!("no stream"),
+                },
+                repl::Command::Break(Some(condition)) => {
+                    println!("breakpoint {}: {}", breakpoints.len() + 1, condition);
+                    breakpoints.push(Breakpoint {
+                        condition,
+                        enabled: true,
+                    });
+                }
+                repl::Command::Break(None) => {
+                    for (i, bp) in breakpoints.iter().enumerate() {
+                        let state = if bp.enabled { "" } else { " (disabled)" };
+                        println!("{}: {}{}", i + 1, bp.condition, state);
+                    }
+                }
+                repl::Command::Toggle(n) => match breakpoints.get_mut(n.wrapping_sub(1)) {
+                    Some(bp) => bp.enabled = !bp.enabled,
+                    None => println!("no breakpoint {}", n),
+                },
+                repl::Command::Delete(n) if (1..=breakpoints.len()).contains(&n) => {
+                    breakpoints.remove(n - 1);
+                }
+                repl::Command::Delete(n) => println!("no breakpoint {}", n),
+                repl::Command::Dump(snapshot) => write_dump(player.frames().decoder(), snapshot),
+                repl::Command::View {
+                    base,
+                    width,
+                    stride,
+                    format,
+                    base8,
+                } => {
+                    let mut view = match std::mem::replace(&mut screen, Screen::Frame) {
+                        Screen::Memory(view) => view,
+                        _ => MemView::new(player.frames().decoder()),
+                    };
+                    view.base = base & 0xff_ffff;
+                    if let Some(width) = width {
+                        view.resize(width as i32 - view.width as i32, 0);
+                    }
+                    view.stride = stride.unwrap_or(view.stride);
+                    view.format = format.unwrap_or(view.format);
+                    view.base8 = base8.map_or(view.base8, |addr| addr & 0xff_ffff);
+                    println!("memory view {}", view);
+                    screen = Screen::Memory(view);
+                }
+                repl::Command::ViewOff => screen = Screen::Frame,
+                repl::Command::Help => println!("{}", repl::HELP),
+                repl::Command::Quit => break 'mainloop,
+            }
+        }
+        if was_playing && !playing {
+            settle(&mut player, &mut pending, &mut full_upload);
+        }
+        was_playing = playing;
+        if let (Some(count), Some(index), Some(path)) = (seek, &index, &args.path) {
+            player.interrupt();
+            let (mut seeked, frame) = index.seek(path, count);
+            pending = Some(frame);
+            // what happened before the keyframe is not known
+            if args.provenance {
+                seeked.decoder_mut().track_writes();
+            }
+            seeked.decoder_mut().track_usage();
+            *player.frames() = seeked;
+            heatmap.clear();
+            full_upload = true;
+        }
+        if cmd_steps > 0 {
+            if player.interrupt() {
+                full_upload = true;
+            }
+            let mut frames = player.frames();
+            let mut last = None;
+            for _ in 0..std::mem::take(&mut cmd_steps) {
+                match frames.step() {
+                    Some(step) => {
+                        if let Some((_, Some(_))) = &last {
+                            // a committed frame is not shown
+                            full_upload = true;
+                        }
+                        if let Some(frame) = &step.1 {
+                            heatmap.push(frame);
+                        }
+                        last = Some(step);
+                    }
+                    None => break,
+                }
+            }
+            match last {
+                Some((result, frame)) => {
+                    let decoder_ctx = frames.decoder();
+                    let text = format!(
+                        "{:08x} {}",
+                        decoder_ctx.get_offset(),
+                        disasm::disasm(decoder_ctx, &result)
+                    );
+                    executed = Some((text, command_rects(decoder_ctx, &result)));
+                    pending = Some(frame.unwrap_or_else(|| frames.partial()));
+                }
+                None => println!("end of stream"),
+            }
+        } else if pending.is_some() || playing | stepping {
+            executed = None;
+        }
+        if pending.is_none() && (playing | stepping) {
+            if playing {
+                player.play(&breakpoints);
+            } else {
+                player.step(&breakpoints);
+            }
+            match player.next() {
+                Some(Reply::Frame(frame, state)) => {
+                    heatmap.push(&frame);
+                    pending = Some(*frame);
+                    shown_state = state;
+                    stepping = false;
+                }
+                Some(Reply::Break(i, result, frame)) => {
+                    player.interrupt();
+                    let frames = player.frames();
+                    let decoder_ctx = frames.decoder();
+                    let text = format!(
+                        "break {}: {} | {:08x} {}",
+                        i + 1,
+                        breakpoints[i].condition,
+                        decoder_ctx.get_offset(),
+                        disasm::disasm(decoder_ctx, &result)
+                    );
+                    println!("{}", text);
+                    executed = Some((text, command_rects(decoder_ctx, &result)));
+                    // the command that hit may have committed a frame
+                    pending = Some(match frame {
+                        Some(frame) => {
+                            heatmap.push(&frame);
+                            *frame
+                        }
+                        None => frames.partial(),
+                    });
+                    playing = false;
+                    stepping = false;
+                }
+                Some(Reply::End) => {
+                    player.interrupt();
+                    if playing {
+                        println!("end of stream");
+                    }
+                    playing = false;
+                    stepping = false;
+                }
+                Some(Reply::Skipped) | None => {}
+            }
+        }
+        // nothing to show before the first mode set
+        if let Some(frame) = pending
+            .take()
+            .filter(|frame| frame.size.0 > 0 && frame.size.1 > 0)
+        {
+            if frame.size != cur_size {
+                let (w, h) = frame.size;
+                // as large as the stream, within the screen
+                let window = canvas.window_mut();
+                if window.fullscreen_state() == FullscreenType::Off {
+                    let bounds = video_subsystem.display_usable_bounds(0).unwrap();
+                    window
+                        .set_size(w.min(bounds.width()), h.min(bounds.height()))
+                        .unwrap();
+                }
+                rendertex = Some(
+                    texture_creator
+                        .create_texture_streaming(PixelFormatEnum::RGB888, w, h)
+                        .unwrap(),
+                );
+                debugtex = Some({
+                    let mut tex = texture_creator
+                        .create_texture_target(PixelFormatEnum::RGBA8888, w, h)
+                        .unwrap();
+                    tex.set_blend_mode(sdl2::render::BlendMode::Blend);
+                    tex.set_alpha_mod(51);
+                    tex
+                });
+                heattex = Some({
+                    let mut tex = texture_creator
+                        .create_texture_streaming(PixelFormatEnum::ARGB8888, w, h)
+                        .unwrap();
+                    tex.set_blend_mode(sdl2::render::BlendMode::Blend);
+                    tex
+                });
+                full_upload = true;
+                println!("output resize: {}x{}", w, h);
+                cur_size = frame.size;
+            }
+            if let Some(tex) = &mut rendertex {
+                let whole = [DamageRect {
+                    x: 0,
+                    y: 0,
+                    w: frame.size.0,
+                    h: frame.size.1,
+                }];
+                let rects = if std::mem::take(&mut full_upload) {
+                    &whole[..]
+                } else {
+                    &frame.damage[..]
+                };
+                for &rect in rects {
+                    upload_rect(tex, &frame, rect);
+                }
+            }
+            if let Some(tex) = &mut debugtex {
+                canvas
+                    .with_texture_canvas(tex, |c| {
+                        c.set_draw_color(Color::RGBA(0, 0, 0, 0));
+                        c.clear();
+                        for span in frame.dbg_spans() {
+                            let [r, g, b] = span.color;
+                            c.set_draw_color(Color::RGB(r, g, b));
+                            let (x, y) = (span.x as i32, span.y as i32);
+                            c.draw_line((x, y), (x + span.len as i32 - 1, y)).unwrap();
+                        }
+                    })
+                    .unwrap();
+            }
+            heat_stale = true;
+            reg_localcopy = frame.reg;
+            addr = (frame.addr16, frame.addr8);
+            framecnt = frame.count;
+            let back = frame.back;
+            shown = Some(frame);
+            if let (Some((back16, back8)), true) = (back, back != back_buffer) {
+                println!(
+                    "frame {}: page flip, back buffer {:06X}/{:06X}",
+                    framecnt, back16, back8
+                );
+            }
+            back_buffer = back;
+        }
+        let window = canvas.output_size().unwrap();
+        let picked = picked.filter(|_| matches!(screen, Screen::Frame));
+        if let Some((x, y)) = picked.and_then(|pos| viewport.to_pixel(cur_size, window, pos)) {
+            let i = y as usize * cur_size.0 as usize + x as usize;
+            let (addr16, addr8) = ((addr.0 + i * 2) & 0xff_ffff, (addr.1 + i) & 0xff_ffff);
+            executed = pixel_provenance(player.frames().decoder(), (x, y), addr16, addr8);
+        }
+
+        canvas.set_draw_color(Color::BLACK);
+        canvas.clear();
+        let state = state_on_screen(&shown, &shown_state);
+        let view_size = screen_size(&screen, &player, state, cur_size);
+        let frame_rect = viewport.frame_rect(view_size, window);
+        // the frame itself does not wait for the decoder
+        let rgb = match screen {
+            Screen::Frame => None,
+            _ => with_state(&player, state, |decoder_ctx| screen.render(decoder_ctx)),
+        };
+        if let Some(rgb) = rgb {
+            let (w, h) = view_size;
+            let stale = imagetex.as_ref().is_none_or(|tex| {
+                let query = tex.query();
+                (query.width, query.height) != (w, h)
+            });
+            if stale {
+                imagetex = Some(
+                    texture_creator
+                        .create_texture_streaming(PixelFormatEnum::RGB888, w, h)
+                        .unwrap(),
+                );
+            }
+            let tex = imagetex.as_mut().unwrap();
+            tex.with_lock(None, |buffer: &mut [u8], pitch: usize| {
+                for (i, p) in rgb.chunks_exact(3).enumerate() {
+                    let pos = i / w as usize * pitch + i % w as usize * 4;
+                    buffer[pos + 2] = p[0];
+                    buffer[pos + 1] = p[1];
+                    buffer[pos] = p[2];
+                }
+            })
+            .unwrap();
+            canvas.copy(tex, None, frame_rect).unwrap();
+        } else if let Screen::Frame = screen {
+            if let Some(tex) = &mut rendertex {
+                canvas.copy(tex, None, frame_rect).unwrap();
+            }
+            if draw_debug {
+                if let Some(tex) = &mut debugtex {
+                    canvas.copy(tex, None, frame_rect).unwrap();
+                }
+            }
+            if show_heatmap {
+                if let Some(tex) = &mut heattex {
+                    // only rendered while shown
+                    if std::mem::take(&mut heat_stale) {
+                        let rgba = heatmap.render();
+                        tex.with_lock(None, |buffer: &mut [u8], _pitch: usize| {
+                            for (i, p) in rgba.chunks_exact(4).enumerate() {
+                                buffer[i * 4 + 3] = p[3];
+                                buffer[i * 4 + 2] = p[0];
+                                buffer[i * 4 + 1] = p[1];
+                                buffer[i * 4] = p[2];
+                            }
+                        })
+                        .unwrap();
+                    }
+                    canvas.copy(tex, None, frame_rect).unwrap();
+                }
+            }
+        }
+        if show_info {
+            draw_text(
+                &mut canvas,
+                &font,
+                (0, 0).into(),
+                &format!("frame: {}", framecnt),
+            );
+            draw_text(
+                &mut canvas,
+                &font,
+                (0, 8).into(),
+                &format!("addr16: {:06X}, addr8: {:06X}", addr.0, addr.1),
+            );
+            for i in 0..256 {
+                let x = i % 16;
+                let y = i / 16;
+                draw_text(
+                    &mut canvas,
+                    &font,
+                    (x * 16, y * 8 + 16).into(),
+                    &format!("{:02X}", reg_localcopy[i as usize]),
+                );
+            }
+        }
+
+        let status_y = window.1 as i32 - 8 - if show_timeline { TIMELINE_HEIGHT } else { 0 };
+        if let Screen::Usage { .. } = screen {
+            draw_legend(&mut canvas, &font, status_y);
+        } else if let Some(status) = (!matches!(screen, Screen::Frame))
+            .then(|| with_state(&player, state, |decoder_ctx| screen.status(decoder_ctx)))
+            .flatten()
+        {
+            draw_text(&mut canvas, &font, (0, status_y).into(), &status);
+        } else if let Some((text, rects)) = &executed {
+            for &(rect, color) in rects {
+                canvas.set_draw_color(color);
+                canvas
+                    .draw_rect(viewport.map_rect(cur_size, window, rect))
+                    .unwrap();
+            }
+            draw_text(&mut canvas, &font, (0, status_y).into(), text);
+        }
+        if let (true, Some(pos)) = (show_pixel, mouse) {
+            let pixel = viewport.to_pixel(view_size, window, pos);
+            let lines = match (&screen, &shown, pixel) {
+                (Screen::Frame, Some(frame), Some(pixel)) => {
+                    let mut lines = pixel_lines(frame, pixel);
+                    if show_heatmap {
+                        let count = heatmap.count((pixel.0 as u32, pixel.1 as u32));
+                        lines.push(format!("writes: {} in {} frames", count, heatmap.frames()));
+                    }
+                    Some(lines)
+                }
+                (_, _, Some((x, y))) => with_state(&player, state, |decoder_ctx| {
+                    screen.describe(decoder_ctx, (x as u32, y as u32))
+                }),
+                _ => None,
+            };
+            if let Some(lines) = lines {
+                draw_lines_at(&mut canvas, &font, &lines, pos);
+            }
+        }
+        let mut legend_y = 0;
+        if show_heatmap && matches!(screen, Screen::Frame) {
+            draw_heat_legend(&mut canvas, &font, heatmap.frames(), args.heatmap);
+            legend_y = 24;
+        }
+        if draw_debug && matches!(screen, Screen::Frame) {
+            draw_dbg_legend(&mut canvas, &font, legend_y);
+        }
+        if show_breakpoints {
+            draw_breakpoints(&mut canvas, &font, &breakpoints, new_breakpoint.as_deref());
+        }
+        if let (true, Some(index)) = (show_timeline, &index) {
+            draw_timeline(&mut canvas, index, framecnt);
+        }
+
+        canvas.present();
+        sleep(frame_duration);
+    }
+    println!("loop finished");
+}
+
+const TIMELINE_HEIGHT: i32 = 8;
+
+/// Size of what `screen` shows, only asking the decoder when it is not the
+/// frame.
+fn screen_size(
+    screen: &Screen,
+    player: &Player,
+    state: Option<&DLDecoder>,
+    frame: (u32, u32),
+) -> (u32, u32) {
+    match screen {
+        Screen::Frame => frame,
+        _ => with_state(player, state, |decoder_ctx| screen.size(decoder_ctx, frame)),
+    }
+}
+
+/// The decoder state copied with the frame on screen, if it was.
+fn state_on_screen<'a>(
+    shown: &Option<Frame>,
+    state: &'a Option<Box<DLDecoder>>,
+) -> Option<&'a DLDecoder> {
+    let (frame, state) = (shown.as_ref()?, state.as_deref()?);
+    (frame.offset == state.get_offset() && frame.count == state.get_frame_count()).then_some(state)
+}
+
+/// Runs `f` on `state`, or on the decoder itself when there is no copy,
+/// waiting for the frame being decoded.
+fn with_state<T>(player: &Player, state: Option<&DLDecoder>, f: impl FnOnce(&DLDecoder) -> T) -> T {
+    match state {
+        Some(state) => f(state),
+        None => f(player.frames().decoder()),
+    }
+}
+
+/// Stops decoding ahead so the decoder state is the one on screen, showing
+/// the frame as far as decoding got.
+fn settle(player: &mut Player, pending: &mut Option<Frame>, full_upload: &mut bool) {
+    if player.interrupt() {
+        *pending = Some(player.frames().partial());
+        *full_upload = true;
+    }
+}
+
+/// Outlines around the pixels a command wrote, and read for memcpy,
+/// one per row.
+fn command_rects(decoder_ctx: &DLDecoder, result: &DLDecoderResult) -> Vec<(Rect, Color)> {
+    let (dst, len, is16, src) = match *result {
+        DLDecoderResult::Fill(addr, len, is16)
+        | DLDecoderResult::Decomp(addr, len, is16)
+        | DLDecoderResult::Raw(addr, len, is16)
+        | DLDecoderResult::Rlx(addr, len, is16) => (addr, len, is16, None),
+        DLDecoderResult::Memcpy(addr, len, is16, src) => (addr, len, is16, Some(src)),
+        _ => return vec![],
+    };
+    let width = decoder_ctx.get_width();
+    let mut rects = vec![];
+    for (addr, color) in [(Some(dst), Color::WHITE), (src, Color::CYAN)] {
+        let Some((mut x, mut y)) = addr.and_then(|a| disasm::position(decoder_ctx, a, is16)) else {
+            continue;
+        };
+        let mut left = len;
+        while left > 0 {
+            let n = left.min(width - x);
+            rects.push((
+                Rect::new(x as i32 - 1, y as i32 - 1, n as u32 + 2, 3),
+                color,
+            ));
+            left -= n;
+            x = 0;
+            y += 1;
+        }
+    }
+    rects
+}
+
+/// Prints the commands that last wrote the pixel at `pos`, following
+/// memcpy back to where the data came from, and returns the overlay for the
+/// last write.
+fn pixel_provenance(
+    decoder_ctx: &DLDecoder,
+    pos: (i32, i32),
+    addr16: usize,
+    addr8: usize,
+) -> Option<(String, Vec<(Rect, Color)>)> {
+    println!("pixel ({},{})", pos.0, pos.1);
+    let describe = |write: &dldecoder::RamWrite| {
+        format!(
+            "{:08x} #{} {}",
+            write.offset,
+            write.index,
+            disasm::describe(decoder_ctx, &write.result)
+        )
+    };
+    let mut last = None;
+    for (plane, addr) in [("16-bit", addr16), ("8-bit", addr8)] {
+        let chain = decoder_ctx.last_writes(addr)?;
+        match chain.first() {
+            Some((_, write)) => {
+                println!("  {} 0x{:06x}: {}", plane, addr, describe(write));
+                if last.is_none() {
+                    let text = format!("({},{}) {}: {}", pos.0, pos.1, plane, describe(write));
+                    last = Some((text, command_rects(decoder_ctx, &write.result)));
+                }
+            }
+            None => println!("  {} 0x{:06x}: no write recorded", plane, addr),
+        }
+        for (addr, write) in chain.iter().skip(1) {
+            println!("    copied from 0x{:06x}: {}", addr, describe(write));
+        }
+    }
+    last
+}
+
+/// Frame count under `x` on a timeline spanning the window width.
+fn timeline_position(index: Option<&StreamIndex>, x: i32, width: u32) -> Option<u64> {
+    let (first, last) = index?.range();
+    let x = x.clamp(0, width as i32) as u64;
+    Some(first + (last - first) * x / width.max(1) as u64)
+}
+
+fn draw_timeline(
+    canvas: &mut sdl2::render::Canvas<sdl2::video::Window>,
+    index: &StreamIndex,
+    framecnt: u64,
+) {
+    let (w, h) = canvas.output_size().unwrap();
+    let (first, last) = index.range();
+    let y = h as i32 - TIMELINE_HEIGHT;
+    let pos = (framecnt.clamp(first, last) - first) * w as u64 / (last - first).max(1);
+    canvas.set_blend_mode(sdl2::render::BlendMode::Blend);
+    canvas.set_draw_color(Color::RGBA(0, 0, 0, 160));
+    canvas
+        .fill_rect(Rect::new(0, y, w, TIMELINE_HEIGHT as u32))
+        .unwrap();
+    canvas.set_draw_color(Color::RGBA(255, 255, 255, 160));
+    canvas
+        .fill_rect(Rect::new(0, y + 2, pos as u32, TIMELINE_HEIGHT as u32 - 4))
+        .unwrap();
+    let x = |count: u64| ((count - first) * w as u64 / (last - first).max(1)) as i32;
+    canvas.set_draw_color(Color::GREEN);
+    for &(count, _) in index.tables() {
+        canvas
+            .draw_line((x(count), y), (x(count), h as i32))
+            .unwrap();
+    }
+    canvas.set_draw_color(Color::YELLOW);
+    for &(count, _, _) in index.modes() {
+        canvas
+            .draw_line((x(count), y), (x(count), h as i32))
+            .unwrap();
+    }
+}
+
+/// Copies the pixels of `rect` into the frame texture.
+fn upload_rect(tex: &mut Texture, frame: &Frame, rect: DamageRect) {
+    let lock = Rect::new(rect.x as i32, rect.y as i32, rect.w, rect.h);
+    let (x0, y0, w) = (rect.x as usize, rect.y as usize, frame.size.0 as usize);
+    tex.with_lock(lock, |buffer: &mut [u8], pitch: usize| {
+        for y in 0..rect.h as usize {
+            for x in 0..rect.w as usize {
+                let [r, g, b] = frame.rgb((y0 + y) * w + x0 + x);
+                let pos = y * pitch + x * 4;
+                buffer[pos + 2] = r;
+                buffer[pos + 1] = g;
+                buffer[pos] = b;
+            }
+        }
+    })
+    .unwrap();
+}
+
+/// Heatmap colour scale and the frames counted, in the top right corner.
+fn draw_heat_legend(
+    canvas: &mut sdl2::render::Canvas<sdl2::video::Window>,
+    font: &Texture,
+    frames: usize,
+    window: u64,
+) {
+    let (w, _) = canvas.output_size().unwrap();
+    let text = format!("heat {}/{} frames", frames, window);
+    let x = w as i32 - 8 * text.len() as i32;
+    draw_text(canvas, font, (x, 0).into(), &text);
+    for i in 0..64 {
+        let [r, g, b] = heatmap::color(i as f32 / 63.0);
+        canvas.set_draw_color(Color::RGB(r, g, b));
+        canvas.fill_rect(Rect::new(x + i, 8, 1, 7)).unwrap();
+    }
+    draw_text(canvas, font, (x + 68, 8).into(), "1/frame");
+}
+
+/// Debug overlay colours for 16-bit and 8-bit commands, in the top right
+/// corner from `y`.
+fn draw_dbg_legend(canvas: &mut sdl2::render::Canvas<sdl2::video::Window>, font: &Texture, y: i32) {
+    let (w, _) = canvas.output_size().unwrap();
+    let legend = dbg_legend();
+    let width = 40 + 8 * legend.iter().map(|(name, _, _)| name.len()).max().unwrap() as i32;
+    let x = w as i32 - width;
+    draw_text(canvas, font, (x, y).into(), "16 8");
+    for (i, (name, color16, color8)) in legend.into_iter().enumerate() {
+        let y = y + 8 + i as i32 * 8;
+        for (n, [r, g, b]) in [color16, color8].into_iter().enumerate() {
+            canvas.set_draw_color(Color::RGB(r, g, b));
+            canvas
+                .fill_rect(Rect::new(x + n as i32 * 24, y, 7, 7))
+                .unwrap();
+        }
+        draw_text(canvas, font, (x + 40, y).into(), name);
+    }
+}
+
+/// Colours of the usage map regions in a row at `y`.
+fn draw_legend(canvas: &mut sdl2::render::Canvas<sdl2::video::Window>, font: &Texture, y: i32) {
+    let mut x = 0;
+    for region in usagemap::LEGEND {
+        let [r, g, b] = usagemap::color(region);
+        canvas.set_draw_color(Color::RGB(r, g, b));
+        canvas.fill_rect(Rect::new(x, y, 8, 8)).unwrap();
+        let name = usagemap::name(region);
+        draw_text(canvas, font, (x + 10, y).into(), name);
+        x += 10 + 8 * name.len() as i32 + 12;
+    }
+}
+
+/// Position, plane addresses and values of `pixel` for the inspector.
+fn pixel_lines(frame: &Frame, (x, y): (i32, i32)) -> Vec<String> {
+    let i = y as usize * frame.size.0 as usize + x as usize;
+    let (h16, b8) = frame.pixel(i);
+    let [r, g, b] = frame.rgb(i);
+    vec![
+        format!("({},{})", x, y),
+        format!(
+            "16: {:06X} = {:04X}",
+            (frame.addr16 + i * 2) & 0xff_ffff,
+            h16
+        ),
+        format!(" 8: {:06X} = {:02X}", (frame.addr8 + i) & 0xff_ffff, b8),
+        format!("rgb: {:02X}{:02X}{:02X}", r, g, b),
+    ]
+}
+
+/// Draws `lines` next to the mouse at `pos`.
+fn draw_lines_at(
+    canvas: &mut sdl2::render::Canvas<sdl2::video::Window>,
+    font: &Texture,
+    lines: &[String],
+    pos: (i32, i32),
+) {
+    // keep the box inside the window
+    let (ww, wh) = canvas.output_size().unwrap();
+    let width = 8 * lines.iter().map(|l| l.len()).max().unwrap() as i32;
+    let height = 8 * lines.len() as i32;
+    let (mx, my) = pos;
+    let tx = if mx + 12 + width > ww as i32 {
+        mx - 4 - width
+    } else {
+        mx + 12
+    };
+    let ty = if my + 12 + height > wh as i32 {
+        my - 4 - height
+    } else {
+        my + 12
+    };
+    for (n, line) in lines.iter().enumerate() {
+        draw_text(canvas, font, (tx, ty + n as i32 * 8).into(), line);
+    }
+}
+
+/// Numbered breakpoint list in the top right corner, followed by the one
+/// being typed.
+fn draw_breakpoints(
+    canvas: &mut sdl2::render::Canvas<sdl2::video::Window>,
+    font: &Texture,
+    breakpoints: &[Breakpoint],
+    entry: Option<&str>,
+) {
+    let mut lines: Vec<String> = breakpoints
+        .iter()
+        .enumerate()
+        .map(|(i, bp)| {
+            let mark = if bp.enabled { '*' } else { ' ' };
+            format!("{}{} {}", mark, i + 1, bp.condition)
+        })
+        .collect();
+    match entry {
+        Some(entry) => lines.push(format!("> {}_", entry)),
+        None if lines.is_empty() => lines.push("no breakpoints, Shift+B adds one".to_string()),
+        None => {}
+    }
+    let (w, _) = canvas.output_size().unwrap();
+    let x = w as i32 - 8 * lines.iter().map(|l| l.len()).max().unwrap_or(0) as i32;
+    for (i, line) in lines.iter().enumerate() {
+        draw_text(canvas, font, (x, i as i32 * 8).into(), line);
+    }
+}
+
+fn write_dump(decoder_ctx: &DLDecoder, snapshot: bool) {
+    let offset = decoder_ctx.get_next_offset();
+    let path = if snapshot {
+        PathBuf::from(format!("snapshot_{:08x}.dls", offset))
+    } else {
+        PathBuf::from(format!("ramdump_{:08x}.bin", offset))
+    };
+    if snapshot {
+        ramdump::write_snapshot(decoder_ctx, &path);
+    } else {
+        ramdump::write(decoder_ctx, &path);
+    }
+    println!(
+        "wrote {}, {}",
+        path.display(),
+        ramdump::resume_hint(decoder_ctx, &path, snapshot)
+    );
+}
@@ -0,0 +1,117 @@
+use sdl2::rect::Rect;
+
+/// How the frame is scaled to the window.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Scaling {
+    /// As large as fits, keeping the aspect ratio.
+    Fit,
+    /// The largest whole multiple that fits, at least 1.
+    Integer,
+    /// Set by the mouse wheel.
+    Free,
+}
+
+/// Placement of the frame in the window.
+pub struct Viewport {
+    pub scaling: Scaling,
+    zoom: f32,
+    // offset from the centered position, in window pixels
+    pan: (f32, f32),
+}
+
+impl Default for Viewport {
+    fn default() -> Self {
+        Self {
+            scaling: Scaling::Fit,
+            zoom: 1.0,
+            pan: (0.0, 0.0),
+        }
+    }
+}
+
+impl Viewport {
+    fn scale(&self, frame: (u32, u32), window: (u32, u32)) -> f32 {
+        let fit =
+            (window.0 as f32 / frame.0.max(1) as f32).min(window.1 as f32 / frame.1.max(1) as f32);
+        match self.scaling {
+            Scaling::Fit => fit,
+            Scaling::Integer => fit.floor().max(1.0),
+            Scaling::Free => self.zoom,
+        }
+    }
+
+    /// Scale and window position of the frame's top left corner.
+    fn placement(&self, frame: (u32, u32), window: (u32, u32)) -> (f32, (f32, f32)) {
+        let scale = self.scale(frame, window);
+        let x = (window.0 as f32 - frame.0 as f32 * scale) / 2.0 + self.pan.0;
+        let y = (window.1 as f32 - frame.1 as f32 * scale) / 2.0 + self.pan.1;
+        (scale, (x, y))
+    }
+
+    /// Where the frame is drawn.
+    pub fn frame_rect(&self, frame: (u32, u32), window: (u32, u32)) -> Rect {
+        let (scale, (x, y)) = self.placement(frame, window);
+        Rect::new(
+            x.round() as i32,
+            y.round() as i32,
+            (frame.0 as f32 * scale).round().max(1.0) as u32,
+            (frame.1 as f32 * scale).round().max(1.0) as u32,
+        )
+    }
+
+    /// Maps a rectangle in frame pixels to the window.
+    pub fn map_rect(&self, frame: (u32, u32), window: (u32, u32), rect: Rect) -> Rect {
+        let (scale, (x, y)) = self.placement(frame, window);
+        let left = (x + rect.x() as f32 * scale).round() as i32;
+        let top = (y + rect.y() as f32 * scale).round() as i32;
+        let right = (x + rect.right() as f32 * scale).round() as i32;
+        let bottom = (y + rect.bottom() as f32 * scale).round() as i32;
+        Rect::new(
+            left,
+            top,
+            (right - left).max(1) as u32,
+            (bottom - top).max(1) as u32,
+        )
+    }
+
+    /// Frame pixel under the window position `pos`, if there is one.
+    pub fn to_pixel(
+        &self,
+        frame: (u32, u32),
+        window: (u32, u32),
+        pos: (i32, i32),
+    ) -> Option<(i32, i32)> {
+        let (scale, (x, y)) = self.placement(frame, window);
+        let px = ((pos.0 as f32 - x) / scale).floor() as i32;
+        let py = ((pos.1 as f32 - y) / scale).floor() as i32;
+        (px >= 0 && py >= 0 && (px as u32) < frame.0 && (py as u32) < frame.1).then_some((px, py))
+    }
+
+    /// Zooms by `steps` wheel clicks, keeping the point under `pos` in
+    /// place.
+    pub fn zoom_at(&mut self, frame: (u32, u32), window: (u32, u32), pos: (i32, i32), steps: i32) {
+        let (scale, (x, y)) = self.placement(frame, window);
+        let zoom = (scale * 1.25f32.powi(steps)).clamp(0.1, 64.0);
+        let (fx, fy) = ((pos.0 as f32 - x) / scale, (pos.1 as f32 - y) / scale);
+        self.scaling = Scaling::Free;
+        self.zoom = zoom;
+        self.pan = (0.0, 0.0);
+        let (_, (cx, cy)) = self.placement(frame, window);
+        self.pan = (pos.0 as f32 - fx * zoom - cx, pos.1 as f32 - fy * zoom - cy);
+    }
+
+    pub fn drag(&mut self, dx: i32, dy: i32) {
+        self.pan.0 += dx as f32;
+        self.pan.1 += dy as f32;
+    }
+
+    /// Switches fit, integer and free scaling in turn, centering the frame.
+    pub fn cycle(&mut self) {
+        self.scaling = match self.scaling {
+            Scaling::Fit => Scaling::Integer,
+            Scaling::Integer => Scaling::Free,
+            Scaling::Free => Scaling::Fit,
+        };
+        self.pan = (0.0, 0.0);
+    }
+}